@@ -0,0 +1,16 @@
+#!/bin/sh
+# Download the Timendus CHIP-8 test suite ROMs run by tests/test_suite.rs into
+# tests/roms, along with the suite's licence. They are not vendored in this
+# repository. Set SUITE_REF to fetch a tag or commit other than main.
+set -eu
+
+ref=${SUITE_REF:-main}
+base="https://raw.githubusercontent.com/Timendus/chip8-test-suite/$ref"
+dir="$(dirname "$0")/../tests/roms"
+
+mkdir -p "$dir"
+for rom in 1-chip8-logo 2-ibm-logo 3-corax+ 4-flags 5-quirks 6-keypad 7-beep; do
+    curl -fsSL -o "$dir/$rom.ch8" "$base/bin/$rom.ch8"
+done
+curl -fsSL -o "$dir/LICENSE" "$base/LICENSE"
+echo "fetched the test suite at $ref into $dir"
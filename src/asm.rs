@@ -0,0 +1,556 @@
+use crate::instruction::Instruction;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+// Includes nested deeper than this are assumed to be a cycle
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+const MNEMONICS: &[&str] = &[
+    "CLS", "RET", "SCR", "SCL", "EXIT", "LOW", "HIGH", "SCD", "SCU", "JP", "CALL", "SE", "SNE",
+    "SAVE", "LOAD", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR", "SUBN", "SHL", "RND", "DRW",
+    "SKP", "SKNP", "PLANE", "AUDIO", "PITCH",
+];
+
+/// An assembly error and the line it was found on, counted from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+/// Assemble Cowgod style `source` into a ROM to be loaded at `origin`.
+/// `INCLUDE` is an error, use `assemble_file` for sources that include others.
+pub fn assemble(source: &str, origin: u16) -> Result<Vec<u8>, AsmError> {
+    let mut no_includes = |path: &str| -> io::Result<String> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("cannot include '{}' here", path),
+        ))
+    };
+    let mut lines = Vec::new();
+    expand("<source>", source, 0, &mut no_includes, &mut lines)?;
+    Assembler::new(origin).run(&lines)
+}
+
+/// Assemble the file at `path`, reading it and anything it includes with
+/// `read`. Include paths are relative to the including file.
+pub fn assemble_file<F>(path: &str, origin: u16, mut read: F) -> Result<Vec<u8>, AsmError>
+where
+    F: FnMut(&str) -> io::Result<String>,
+{
+    let source = read(path).map_err(|err| AsmError {
+        file: path.to_string(),
+        line: 0,
+        message: err.to_string(),
+    })?;
+    let mut lines = Vec::new();
+    expand(path, &source, 0, &mut read, &mut lines)?;
+    Assembler::new(origin).run(&lines)
+}
+
+// A line of source with comments removed, after includes are expanded
+struct Line {
+    file: String,
+    number: usize,
+    text: String,
+}
+
+impl Line {
+    fn error(&self, message: String) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: self.number,
+            message,
+        }
+    }
+}
+
+fn expand<F>(
+    file: &str,
+    source: &str,
+    depth: usize,
+    read: &mut F,
+    lines: &mut Vec<Line>,
+) -> Result<(), AsmError>
+where
+    F: FnMut(&str) -> io::Result<String>,
+{
+    for (i, text) in source.lines().enumerate() {
+        let line = Line {
+            file: file.to_string(),
+            number: i + 1,
+            text: strip_comment(text).trim().to_string(),
+        };
+        let (word, rest) = split_word(&line.text);
+        if !word.eq_ignore_ascii_case("INCLUDE") {
+            lines.push(line);
+            continue;
+        }
+        let name = rest.trim().trim_matches('"');
+        if name.is_empty() {
+            return Err(line.error(String::from("INCLUDE needs a file name")));
+        }
+        if depth >= MAX_INCLUDE_DEPTH {
+            return Err(line.error(format!("includes nested too deeply at '{}'", name)));
+        }
+        let path = Path::new(file).parent().unwrap_or(Path::new("")).join(name);
+        let path = path.to_string_lossy();
+        let included =
+            read(&path).map_err(|err| line.error(format!("could not read '{}': {}", path, err)))?;
+        expand(&path, &included, depth + 1, read, lines)?;
+    }
+    Ok(())
+}
+
+// Everything before a `;` that is not inside quotes
+fn strip_comment(text: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &text[..i],
+            _ => {}
+        }
+    }
+    text
+}
+
+fn split_word(text: &str) -> (&str, &str) {
+    match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], text[i..].trim_start()),
+        None => (text, ""),
+    }
+}
+
+// Register and operand names can't be symbols, they would always parse as operands
+fn is_symbol(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && matches!(Operand::parse(text), Operand::Expr(_))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand<'a> {
+    V(u8),
+    I,
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    Long(&'a str),
+    Expr(&'a str),
+}
+
+impl<'a> Operand<'a> {
+    fn parse(text: &'a str) -> Operand<'a> {
+        let upper = text.to_ascii_uppercase();
+        match upper.as_str() {
+            "I" => return Operand::I,
+            "[I]" => return Operand::IndirectI,
+            "DT" => return Operand::Dt,
+            "ST" => return Operand::St,
+            "K" => return Operand::K,
+            "F" => return Operand::F,
+            "HF" => return Operand::Hf,
+            "B" => return Operand::B,
+            "R" => return Operand::R,
+            _ => {}
+        }
+        if upper.len() == 2 && upper.starts_with('V') {
+            if let Some(reg) = upper[1..].chars().next().and_then(|c| c.to_digit(16)) {
+                return Operand::V(reg as u8);
+            }
+        }
+        let (word, rest) = split_word(text);
+        if word.eq_ignore_ascii_case("LONG") && !rest.is_empty() {
+            return Operand::Long(rest);
+        }
+        Operand::Expr(text)
+    }
+}
+
+// One instruction or data directive waiting for the second pass
+struct Statement<'a> {
+    line: &'a Line,
+    mnemonic: String,
+    operands: Vec<&'a str>,
+}
+
+struct Assembler {
+    origin: u16,
+    symbols: HashMap<String, i64>,
+}
+
+impl Assembler {
+    fn new(origin: u16) -> Self {
+        Assembler {
+            origin,
+            symbols: HashMap::new(),
+        }
+    }
+
+    fn run(mut self, lines: &[Line]) -> Result<Vec<u8>, AsmError> {
+        // First pass lays out addresses for labels, second encodes
+        let mut statements = Vec::new();
+        let mut addr = self.origin as i64;
+        for line in lines {
+            let mut text = line.text.as_str();
+            while let Some((label, rest)) = text
+                .split_once(':')
+                .filter(|(label, _)| is_symbol(label.trim()))
+            {
+                self.define(line, label.trim(), addr)?;
+                text = rest.trim();
+            }
+            if text.is_empty() {
+                continue;
+            }
+            let (word, rest) = split_word(text);
+            let (name, value) = split_word(rest);
+            if name.eq_ignore_ascii_case("EQU") {
+                if !is_symbol(word) {
+                    return Err(line.error(format!("bad constant name '{}'", word)));
+                }
+                let value = self.eval(value).map_err(|err| line.error(err))?;
+                self.define(line, word, value)?;
+                continue;
+            }
+            let mnemonic = word.to_ascii_uppercase();
+            let operands: Vec<&str> = if rest.is_empty() {
+                Vec::new()
+            } else {
+                rest.split(',').map(str::trim).collect()
+            };
+            addr += match mnemonic.as_str() {
+                "DB" => operands.len() as i64,
+                "DW" => operands.len() as i64 * 2,
+                "LD" if operands.len() == 2
+                    && matches!(Operand::parse(operands[1]), Operand::Long(_)) =>
+                {
+                    4
+                }
+                _ => 2,
+            };
+            statements.push(Statement {
+                line,
+                mnemonic,
+                operands,
+            });
+        }
+
+        let mut rom = Vec::new();
+        for statement in &statements {
+            let line = statement.line;
+            match statement.mnemonic.as_str() {
+                "DB" => {
+                    for operand in &statement.operands {
+                        rom.push(self.byte(operand).map_err(|err| line.error(err))?);
+                    }
+                }
+                "DW" => {
+                    for operand in &statement.operands {
+                        let word = self
+                            .number(operand, 0xFFFF)
+                            .map_err(|err| line.error(err))?;
+                        rom.extend(word.to_be_bytes());
+                    }
+                }
+                _ => rom.extend(
+                    self.instruction(statement)
+                        .map_err(|err| line.error(err))?
+                        .encode(),
+                ),
+            }
+        }
+        Ok(rom)
+    }
+
+    fn define(&mut self, line: &Line, name: &str, value: i64) -> Result<(), AsmError> {
+        if self.symbols.insert(name.to_string(), value).is_some() {
+            return Err(line.error(format!("'{}' is already defined", name)));
+        }
+        Ok(())
+    }
+
+    fn instruction(&self, statement: &Statement) -> Result<Instruction, String> {
+        use Operand::*;
+        let operands: Vec<Operand> = statement
+            .operands
+            .iter()
+            .map(|text| Operand::parse(text))
+            .collect();
+        let instruction = match (statement.mnemonic.as_str(), operands.as_slice()) {
+            ("CLS", []) => Instruction::Clear,
+            ("RET", []) => Instruction::Return,
+            ("SCR", []) => Instruction::ScrollRight,
+            ("SCL", []) => Instruction::ScrollLeft,
+            ("EXIT", []) => Instruction::Exit,
+            ("LOW", []) => Instruction::LowRes,
+            ("HIGH", []) => Instruction::HighRes,
+            ("AUDIO", []) => Instruction::Audio,
+            ("SCD", [Expr(n)]) => Instruction::ScrollDown(self.nibble(n)?),
+            ("SCU", [Expr(n)]) => Instruction::ScrollUp(self.nibble(n)?),
+            ("JP", [Expr(addr)]) => Instruction::Jump(self.addr(addr)?),
+            ("JP", [V(0), Expr(addr)]) => Instruction::JumpOffset(self.addr(addr)?),
+            ("CALL", [Expr(addr)]) => Instruction::Call(self.addr(addr)?),
+            ("SE", [V(x), V(y)]) => Instruction::SkipEq { x: *x, y: *y },
+            ("SE", [V(x), Expr(byte)]) => Instruction::SkipEqImm {
+                x: *x,
+                byte: self.byte(byte)?,
+            },
+            ("SNE", [V(x), V(y)]) => Instruction::SkipNe { x: *x, y: *y },
+            ("SNE", [V(x), Expr(byte)]) => Instruction::SkipNeImm {
+                x: *x,
+                byte: self.byte(byte)?,
+            },
+            ("SAVE", [V(x), V(y)]) => Instruction::SaveRange { x: *x, y: *y },
+            ("LOAD", [V(x), V(y)]) => Instruction::LoadRange { x: *x, y: *y },
+            ("LD", [V(x), V(y)]) => Instruction::Move { x: *x, y: *y },
+            ("LD", [V(x), Expr(byte)]) => Instruction::LoadImm {
+                x: *x,
+                byte: self.byte(byte)?,
+            },
+            ("LD", [I, Expr(addr)]) => Instruction::LoadIndex(self.addr(addr)?),
+            ("LD", [I, Long(addr)]) => Instruction::LongIndex(self.number(addr, 0xFFFF)?),
+            ("LD", [V(x), Dt]) => Instruction::LoadDelay { x: *x },
+            ("LD", [V(x), K]) => Instruction::WaitKey { x: *x },
+            ("LD", [Dt, V(x)]) => Instruction::SetDelay { x: *x },
+            ("LD", [St, V(x)]) => Instruction::SetSound { x: *x },
+            ("LD", [F, V(x)]) => Instruction::Font { x: *x },
+            ("LD", [Hf, V(x)]) => Instruction::BigFont { x: *x },
+            ("LD", [B, V(x)]) => Instruction::Bcd { x: *x },
+            ("LD", [IndirectI, V(x)]) => Instruction::Store { x: *x },
+            ("LD", [V(x), IndirectI]) => Instruction::Load { x: *x },
+            ("LD", [R, V(x)]) => Instruction::StoreFlags { x: *x },
+            ("LD", [V(x), R]) => Instruction::LoadFlags { x: *x },
+            ("ADD", [V(x), V(y)]) => Instruction::Add { x: *x, y: *y },
+            ("ADD", [V(x), Expr(byte)]) => Instruction::AddImm {
+                x: *x,
+                byte: self.byte(byte)?,
+            },
+            ("ADD", [I, V(x)]) => Instruction::AddIndex { x: *x },
+            ("OR", [V(x), V(y)]) => Instruction::Or { x: *x, y: *y },
+            ("AND", [V(x), V(y)]) => Instruction::And { x: *x, y: *y },
+            ("XOR", [V(x), V(y)]) => Instruction::Xor { x: *x, y: *y },
+            ("SUB", [V(x), V(y)]) => Instruction::Sub { x: *x, y: *y },
+            ("SUBN", [V(x), V(y)]) => Instruction::SubN { x: *x, y: *y },
+            // The source register defaults to the shifted one
+            ("SHR", [V(x)]) => Instruction::ShiftRight { x: *x, y: *x },
+            ("SHR", [V(x), V(y)]) => Instruction::ShiftRight { x: *x, y: *y },
+            ("SHL", [V(x)]) => Instruction::ShiftLeft { x: *x, y: *x },
+            ("SHL", [V(x), V(y)]) => Instruction::ShiftLeft { x: *x, y: *y },
+            ("RND", [V(x), Expr(byte)]) => Instruction::Random {
+                x: *x,
+                byte: self.byte(byte)?,
+            },
+            ("DRW", [V(x), V(y), Expr(n)]) => Instruction::Draw {
+                x: *x,
+                y: *y,
+                n: self.nibble(n)?,
+            },
+            ("SKP", [V(x)]) => Instruction::SkipKey { x: *x },
+            ("SKNP", [V(x)]) => Instruction::SkipNotKey { x: *x },
+            // Only the low two bits select planes, but any nibble decodes as PLANE
+            ("PLANE", [Expr(n)]) => Instruction::Plane(self.nibble(n)?),
+            ("PITCH", [V(x)]) => Instruction::Pitch { x: *x },
+            (mnemonic, _) if MNEMONICS.contains(&mnemonic) => {
+                return Err(format!(
+                    "bad operands for {}: '{}'",
+                    mnemonic,
+                    statement.operands.join(", ")
+                ))
+            }
+            (mnemonic, _) => return Err(format!("unknown instruction '{}'", mnemonic)),
+        };
+        Ok(instruction)
+    }
+
+    fn addr(&self, text: &str) -> Result<u16, String> {
+        self.number(text, 0xFFF)
+    }
+
+    fn nibble(&self, text: &str) -> Result<u8, String> {
+        Ok(self.number(text, 0xF)? as u8)
+    }
+
+    // Negative bytes are allowed and stored as two's complement
+    fn byte(&self, text: &str) -> Result<u8, String> {
+        match self.eval(text)? {
+            value @ -128..=255 => Ok(value as u8),
+            value => Err(format!("{} does not fit in a byte", value)),
+        }
+    }
+
+    fn number(&self, text: &str, max: u16) -> Result<u16, String> {
+        match self.eval(text)? {
+            value if (0..=max as i64).contains(&value) => Ok(value as u16),
+            value => Err(format!(
+                "{} is out of range, the most allowed is 0x{:X}",
+                value, max
+            )),
+        }
+    }
+
+    // Numbers and symbols added and subtracted, left to right
+    fn eval(&self, text: &str) -> Result<i64, String> {
+        let mut total = 0i64;
+        let mut sign = 1;
+        let mut start = 0;
+        for (i, c) in text.char_indices() {
+            if c != '+' && c != '-' {
+                continue;
+            }
+            let term = text[start..i].trim();
+            if !term.is_empty() {
+                total += sign * self.term(term)?;
+                sign = 1;
+            }
+            if c == '-' {
+                sign = -sign;
+            }
+            start = i + 1;
+        }
+        let term = text[start..].trim();
+        if term.is_empty() {
+            return Err(format!("bad expression '{}'", text));
+        }
+        Ok(total + sign * self.term(term)?)
+    }
+
+    fn term(&self, text: &str) -> Result<i64, String> {
+        let number = if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X"))
+        {
+            i64::from_str_radix(hex, 16).ok()
+        } else if let Some(bin) = text.strip_prefix("0b").or_else(|| text.strip_prefix("0B")) {
+            i64::from_str_radix(bin, 2).ok()
+        } else if text.starts_with(|c: char| c.is_ascii_digit()) {
+            text.parse().ok()
+        } else if is_symbol(text) {
+            return self
+                .symbols
+                .get(text)
+                .copied()
+                .ok_or_else(|| format!("undefined symbol '{}'", text));
+        } else {
+            None
+        };
+        number.ok_or_else(|| format!("bad number '{}'", text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sources by file name, for assemble_file
+    fn files<'a>(files: &'a [(&'a str, &'a str)]) -> impl FnMut(&str) -> io::Result<String> + 'a {
+        |path| match files.iter().find(|(name, _)| *name == path) {
+            Some((_, source)) => Ok(source.to_string()),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "not found")),
+        }
+    }
+
+    #[test]
+    fn every_instruction_round_trips() {
+        let opcodes = [
+            0x00C3, 0x00D4, 0x00E0, 0x00EE, 0x00FB, 0x00FC, 0x00FD, 0x00FE, 0x00FF, 0x1234, 0x2ABC,
+            0x3A12, 0x4B34, 0x5120, 0x5122, 0x5123, 0x60FF, 0x7F01, 0x8120, 0x8121, 0x8122, 0x8123,
+            0x8124, 0x8125, 0x8126, 0x8127, 0x812E, 0x9340, 0xA123, 0xB300, 0xC7AA, 0xD125, 0xE59E,
+            0xE6A1, 0xF201, 0xF002, 0xF107, 0xF20A, 0xF315, 0xF418, 0xF11E, 0xF229, 0xF330, 0xF433,
+            0xF53A, 0xF555, 0xF665, 0xF775, 0xF885, 0x5121, 0xF501,
+        ];
+        let mut instructions: Vec<Instruction> = opcodes
+            .iter()
+            .map(|&opcode| Instruction::decode(opcode, None))
+            .collect();
+        instructions.push(Instruction::decode(0xF000, Some(0xBEEF)));
+        for instruction in instructions {
+            let rom = assemble(&instruction.to_string(), 0x200)
+                .unwrap_or_else(|err| panic!("{}: {}", instruction, err));
+            assert_eq!(rom, instruction.encode(), "{}", instruction);
+            assert_eq!(Instruction::decode_at(&rom, 0), Some(instruction));
+        }
+    }
+
+    // Anything the disassembler prints has to assemble back to the same bytes
+    #[test]
+    fn every_decoded_opcode_reassembles() {
+        for opcode in 0..=u16::MAX {
+            let instruction = Instruction::decode(opcode, Some(0x1234));
+            let rom = assemble(&instruction.to_string(), 0x200)
+                .unwrap_or_else(|err| panic!("{:04X} {}: {}", opcode, instruction, err));
+            assert_eq!(rom, instruction.encode(), "{:04X} {}", opcode, instruction);
+        }
+    }
+
+    #[test]
+    fn labels_can_be_used_before_they_are_defined() {
+        let source = "JP end\nloop: CLS\nJP loop\nend: LD I, sprite\nsprite: DB 0xF0";
+        assert_eq!(
+            assemble(source, 0x200),
+            Ok(vec![0x12, 0x06, 0x00, 0xE0, 0x12, 0x02, 0xA2, 0x08, 0xF0])
+        );
+    }
+
+    #[test]
+    fn operand_names_are_not_symbols() {
+        for name in ["K", "f", "B", "R", "I", "DT", "st", "HF", "V0", "va"] {
+            assert!(!is_symbol(name), "{}", name);
+            assert!(assemble(&format!("{} EQU 1", name), 0x200).is_err());
+        }
+        assert!(is_symbol("KEY"));
+        assert!(is_symbol("V10"));
+    }
+
+    #[test]
+    fn errors_report_the_line_they_are_on() {
+        let err = assemble("CLS\n\n  ; comment\nJP nowhere\n", 0x200).unwrap_err();
+        assert_eq!(err.line, 4);
+        assert_eq!(err.to_string(), "<source>:4: undefined symbol 'nowhere'");
+
+        let sources = [
+            ("main.asm", "CLS\nINCLUDE lib.asm\nRET"),
+            ("lib.asm", "RET\nFROB V1"),
+        ];
+        let err = assemble_file("main.asm", 0x200, files(&sources)).unwrap_err();
+        assert_eq!(err.to_string(), "lib.asm:2: unknown instruction 'FROB'");
+    }
+
+    #[test]
+    fn includes_are_expanded_in_place() {
+        let sources = [
+            ("main.asm", "CLS\nINCLUDE \"lib.asm\"\nJP done"),
+            ("lib.asm", "done: EXIT"),
+        ];
+        assert_eq!(
+            assemble_file("main.asm", 0x200, files(&sources)),
+            Ok(vec![0x00, 0xE0, 0x00, 0xFD, 0x12, 0x02])
+        );
+    }
+
+    #[test]
+    fn include_cycles_are_an_error() {
+        let sources = [("a.asm", "INCLUDE b.asm"), ("b.asm", "CLS\nINCLUDE a.asm")];
+        let err = assemble_file("a.asm", 0x200, files(&sources)).unwrap_err();
+        assert!(err.message.contains("nested too deeply"), "{}", err);
+    }
+}
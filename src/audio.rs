@@ -0,0 +1,268 @@
+use crate::chip::{Chip8, AUDIO_PATTERN_SIZE};
+use std::io::{self, Seek, SeekFrom, Write};
+
+/// Samples per second of everything generated here, mono signed 16 bit.
+pub const SAMPLE_RATE: u32 = 44_100;
+/// Samples generated for each 60hz frame.
+pub const SAMPLES_PER_FRAME: usize = SAMPLE_RATE as usize / 60;
+
+/// Somewhere to send generated samples, a file or a sound device.
+pub trait AudioBackend {
+    fn write(&mut self, samples: &[i16]) -> io::Result<()>;
+
+    /// Called once after the last samples, before the backend is dropped.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+const PATTERN_BITS: usize = AUDIO_PATTERN_SIZE * 8;
+// The plain beep is one period of a square wave
+const SQUARE_WAVE: [u8; AUDIO_PATTERN_SIZE] = [
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+/// Bits per second the XO-CHIP audio pattern is played at for `pitch`,
+/// 4000 at the default of 64 and doubling every 48.
+pub fn pattern_rate(pitch: u8) -> f64 {
+    4000.0 * 2f64.powf((pitch as f64 - 64.0) / 48.0)
+}
+
+/// Sound played while the sound timer is running. A square wave at a fixed
+/// frequency, or the XO-CHIP audio pattern at its pitch once a ROM loads one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tone {
+    frequency: f64,
+    amplitude: f64,
+    // Position within the pattern, 0 to 1
+    phase: f64,
+}
+
+impl Tone {
+    /// `volume` is from 0 to 1.
+    pub fn new(frequency: f64, volume: f64) -> Self {
+        Tone {
+            frequency,
+            amplitude: volume.clamp(0.0, 1.0) * i16::MAX as f64,
+            phase: 0.0,
+        }
+    }
+
+    /// A frame of samples for `chip8`, call before running the frame. Silent
+    /// unless the sound timer is above zero.
+    pub fn frame(&mut self, chip8: &Chip8) -> Vec<i16> {
+        let (_, sound_timer) = chip8.timers();
+        if sound_timer == 0 {
+            // Start the next beep at the beginning of a period
+            self.phase = 0.0;
+            return vec![0; SAMPLES_PER_FRAME];
+        }
+        let (pattern, frequency) = match chip8.audio_pattern() {
+            Some(pattern) => (pattern, pattern_rate(chip8.pitch()) / PATTERN_BITS as f64),
+            None => (&SQUARE_WAVE, self.frequency),
+        };
+        let step = frequency / SAMPLE_RATE as f64;
+        (0..SAMPLES_PER_FRAME)
+            .map(|_| {
+                let bit = (self.phase * PATTERN_BITS as f64) as usize;
+                let high = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+                self.phase = (self.phase + step).fract();
+                if high {
+                    self.amplitude as i16
+                } else {
+                    -self.amplitude as i16
+                }
+            })
+            .collect()
+    }
+}
+
+// Most sample bytes the 32 bit RIFF size can describe, a whole number of samples
+const MAX_DATA_LEN: u32 = (u32::MAX - 36) & !1;
+
+/// Writes samples to a WAV file. The header sizes are filled in by `finish`.
+/// Samples past the largest size a WAV file can hold, about 13.5 hours, are
+/// dropped.
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    data_len: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut out: W) -> io::Result<Self> {
+        out.write_all(&header(0))?;
+        Ok(WavWriter { out, data_len: 0 })
+    }
+
+    /// The underlying writer, once `finish` has filled in the header.
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write + Seek> AudioBackend for WavWriter<W> {
+    fn write(&mut self, samples: &[i16]) -> io::Result<()> {
+        let room = (MAX_DATA_LEN - self.data_len) as usize / 2;
+        let bytes: Vec<u8> = samples
+            .iter()
+            .take(room)
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        self.out.write_all(&bytes)?;
+        self.data_len += bytes.len() as u32;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.out.seek(SeekFrom::Start(0))?;
+        self.out.write_all(&header(self.data_len))?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()
+    }
+}
+
+// RIFF header for `data_len` bytes of mono 16 bit PCM
+fn header(data_len: u32) -> [u8; 44] {
+    let mut header = [0; 44];
+    let fields: [(&[u8], usize); 13] = [
+        (b"RIFF", 0),
+        (&data_len.saturating_add(36).to_le_bytes(), 4),
+        (b"WAVE", 8),
+        (b"fmt ", 12),
+        (&16u32.to_le_bytes(), 16),
+        // PCM, one channel
+        (&1u16.to_le_bytes(), 20),
+        (&1u16.to_le_bytes(), 22),
+        (&SAMPLE_RATE.to_le_bytes(), 24),
+        // Byte rate and block size
+        (&(SAMPLE_RATE * 2).to_le_bytes(), 28),
+        (&2u16.to_le_bytes(), 32),
+        (&16u16.to_le_bytes(), 34),
+        (b"data", 36),
+        (&data_len.to_le_bytes(), 40),
+    ];
+    for (bytes, offset) in fields {
+        header[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+    use std::io::Cursor;
+
+    // A machine with the sound timer at `sound_timer`
+    fn machine(sound_timer: u8) -> Chip8 {
+        let mut chip8 = Chip8::with_seed(Quirks::xochip(), 0);
+        // LD V0, sound_timer; LD ST, V0
+        chip8.load(&[0x60, sound_timer, 0xF0, 0x18], 0x200).unwrap();
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        chip8
+    }
+
+    // A machine playing `pattern` at `pitch` loaded with AUDIO
+    fn playing(pattern: [u8; AUDIO_PATTERN_SIZE], pitch: u8) -> Chip8 {
+        let mut chip8 = Chip8::with_seed(Quirks::xochip(), 0);
+        // LD I, 0x20C; AUDIO; LD V0, pitch; PITCH V0; LD V0, 10; LD ST, V0
+        let program = [
+            0xA2, 0x0C, 0xF0, 0x02, 0x60, pitch, 0xF0, 0x3A, 0x60, 0x0A, 0xF0, 0x18,
+        ];
+        // Loading sets pc, so the program goes last
+        chip8.load(&pattern, 0x20C).unwrap();
+        chip8.load(&program, 0x200).unwrap();
+        for _ in 0..program.len() / 2 {
+            chip8.step().unwrap();
+        }
+        chip8
+    }
+
+    #[test]
+    fn pattern_rate_doubles_every_48() {
+        assert_eq!(pattern_rate(64), 4000.0);
+        assert_eq!(pattern_rate(112), 8000.0);
+        assert_eq!(pattern_rate(16), 2000.0);
+    }
+
+    #[test]
+    fn patterns_play_at_their_pitch() {
+        let mut pattern = [0; AUDIO_PATTERN_SIZE];
+        pattern[0] = 0xF0;
+        // Four bits high at 4000 bits a second last 44.1 samples
+        let samples = Tone::new(440.0, 1.0).frame(&playing(pattern, 64));
+        let mut expected = vec![-i16::MAX; SAMPLES_PER_FRAME];
+        expected[..45].fill(i16::MAX);
+        assert_eq!(samples, expected);
+
+        // Twice as fast an octave up, so the pattern starts again at sample 705.6
+        let samples = Tone::new(440.0, 1.0).frame(&playing(pattern, 112));
+        let mut expected = vec![-i16::MAX; SAMPLES_PER_FRAME];
+        expected[..23].fill(i16::MAX);
+        expected[706..728].fill(i16::MAX);
+        assert_eq!(samples, expected);
+    }
+
+    #[test]
+    fn loaded_silent_patterns_do_not_beep() {
+        let samples = Tone::new(440.0, 1.0).frame(&playing([0; AUDIO_PATTERN_SIZE], 64));
+        assert!(samples.iter().all(|&sample| sample == -i16::MAX));
+    }
+
+    #[test]
+    fn silent_without_the_sound_timer() {
+        let samples = Tone::new(440.0, 1.0).frame(&machine(0));
+        assert_eq!(samples, vec![0; SAMPLES_PER_FRAME]);
+    }
+
+    #[test]
+    fn square_wave_period_matches_the_frequency() {
+        // 100 samples a period
+        let samples = Tone::new(441.0, 1.0).frame(&machine(10));
+        assert_eq!(samples.len(), SAMPLES_PER_FRAME);
+        for period in samples.chunks_exact(100) {
+            assert!(period[..45].iter().all(|&sample| sample == i16::MAX));
+            assert!(period[55..].iter().all(|&sample| sample == -i16::MAX));
+        }
+    }
+
+    #[test]
+    fn wav_sizes_are_filled_in_on_finish() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new())).unwrap();
+        for _ in 0..3 {
+            wav.write(&[1; SAMPLES_PER_FRAME]).unwrap();
+        }
+        wav.finish().unwrap();
+        let bytes = wav.into_inner().into_inner();
+        let data_len = 3 * SAMPLES_PER_FRAME as u32 * 2;
+        let field =
+            |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        assert_eq!(bytes.len(), 44 + data_len as usize);
+        assert_eq!(&bytes[..4], b"RIFF");
+        assert_eq!(field(4), 36 + data_len);
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(field(40), data_len);
+        assert_eq!(&bytes[44..46], &[1, 0]);
+    }
+
+    #[test]
+    fn header_sizes_saturate() {
+        let header = header(u32::MAX);
+        assert_eq!(&header[4..8], &u32::MAX.to_le_bytes());
+        assert_eq!(&header[40..44], &u32::MAX.to_le_bytes());
+    }
+
+    #[test]
+    fn samples_past_the_size_limit_are_dropped() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new())).unwrap();
+        wav.data_len = MAX_DATA_LEN - 2;
+        wav.write(&[1, 2, 3]).unwrap();
+        assert_eq!(wav.data_len, MAX_DATA_LEN);
+        wav.write(&[4]).unwrap();
+        wav.finish().unwrap();
+        let bytes = wav.into_inner().into_inner();
+        assert_eq!(&bytes[44..], &[1, 0]);
+        assert_eq!(&bytes[4..8], &(MAX_DATA_LEN + 36).to_le_bytes());
+    }
+}
@@ -1,16 +1,66 @@
-use rand::Rng;
-use std::io::{self, Write};
+use crate::error::Chip8Error;
+use crate::framebuffer::{Framebuffer, HIRES_HEIGHT, HIRES_WIDTH, LORES_HEIGHT, LORES_WIDTH};
+use crate::instruction::Instruction;
+use crate::quirks::{IndexIncrement, Quirks};
+use crate::rng::{self, Rng};
+use crate::state::{StateError, StateReader, StateWriter};
+use std::io;
 
-const MEMORY_SIZE: usize = 4096;
-const PROGRAM_START: usize = 0x200;
+// Enough for XO-CHIP, the other platforms only address the first 4k, see `Quirks::memory_size`
+const MEMORY_SIZE: usize = 0x10000;
+pub const PROGRAM_START: u16 = 0x200;
 const NUM_REGISTERS: usize = 16;
 const STACK_SIZE: usize = 16;
 const NUM_KEYS: usize = 16;
-const DISPLAY_WIDTH: usize = 64;
-const DISPLAY_HEIGHT: usize = 32;
+const NUM_RPL_FLAGS: usize = 16;
+const BIG_FONT_START: usize = 0x50;
+pub const AUDIO_PATTERN_SIZE: usize = 16;
+const DEFAULT_PITCH: u8 = 64; // 4000hz playback of the audio pattern
+
+/// Result of successfully executing a single instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    Executed,
+    WaitingForKey,
+    WaitingForVblank,
+    Exit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// A run of memory an instruction read or wrote through the index register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub addr: usize,
+    pub len: usize,
+    pub kind: AccessKind,
+}
+
+// Faults raised by op code functions, given the pc and opcode by `emulate`
+enum Fault {
+    InvalidOpcode,
+    StackOverflow,
+    StackUnderflow,
+    MemoryOutOfBounds(usize),
+}
+
+impl Fault {
+    fn at(self, pc: u16, opcode: u16) -> Chip8Error {
+        match self {
+            Fault::InvalidOpcode => Chip8Error::InvalidOpcode { pc, opcode },
+            Fault::StackOverflow => Chip8Error::StackOverflow { pc, opcode },
+            Fault::StackUnderflow => Chip8Error::StackUnderflow { pc, opcode },
+            Fault::MemoryOutOfBounds(addr) => Chip8Error::MemoryOutOfBounds { pc, opcode, addr },
+        }
+    }
+}
 
 pub struct Chip8 {
-    memory: [u8; MEMORY_SIZE],      // 4k memory
+    memory: [u8; MEMORY_SIZE],
     registers: [u8; NUM_REGISTERS], // registers V0 -> VF
     index: u16,
     pc: u16,
@@ -19,22 +69,56 @@ pub struct Chip8 {
     delay_timer: u8,
     sound_timer: u8,
     inputs: [bool; NUM_KEYS],
-    display: [[bool; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+    waiting_for_key: bool,
+    released_key: Option<u8>,
+    display: Framebuffer,
+    planes: u8,                     // XO-CHIP bit planes selected for drawing
+    rpl_flags: [u8; NUM_RPL_FLAGS], // SUPER-CHIP HP48 user flags
+    audio_pattern: [u8; AUDIO_PATTERN_SIZE],
+    audio_pattern_loaded: bool, // Set once by AUDIO
+    pitch: u8,
+    display_dirty: bool,
+    // Memory accesses by the last instruction, only recorded when enabled
+    record_accesses: bool,
+    memory_accesses: Vec<MemoryAccess>,
+    quirks: Quirks,
+    // Set by DRW under the display wait quirk, cleared by the next timer tick
+    waiting_for_vblank: bool,
+    rng: Rng,
 }
 
 impl Chip8 {
-    pub fn new() -> Self {
+    /// A machine with a random seed for `RND`, see `with_seed` for repeatable runs.
+    pub fn new(quirks: Quirks) -> Self {
+        Chip8::with_seed(quirks, rng::random_seed())
+    }
+
+    /// A machine whose `RND` results are the same every run with `seed`.
+    pub fn with_seed(quirks: Quirks, seed: u64) -> Self {
         let mut chip8 = Chip8 {
             memory: [0; MEMORY_SIZE],
             registers: [0; NUM_REGISTERS],
             index: 0,
-            pc: 0x200, // First 512 bytes held chip8 interpreter
+            pc: PROGRAM_START, // First 512 bytes held chip8 interpreter
             stack: [0; STACK_SIZE],
             stack_pointer: 0,
             delay_timer: 0,
             sound_timer: 0,
             inputs: [false; NUM_KEYS],
-            display: [[false; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+            waiting_for_key: false,
+            released_key: None,
+            display: Framebuffer::new(LORES_WIDTH, LORES_HEIGHT),
+            planes: 1,
+            rpl_flags: [0; NUM_RPL_FLAGS],
+            audio_pattern: [0; AUDIO_PATTERN_SIZE],
+            audio_pattern_loaded: false,
+            pitch: DEFAULT_PITCH,
+            display_dirty: true,
+            record_accesses: false,
+            memory_accesses: Vec::new(),
+            quirks,
+            waiting_for_vblank: false,
+            rng: Rng::new(seed),
         };
 
         let font_set: [u8; 80] = [
@@ -56,36 +140,274 @@ impl Chip8 {
             0xF0, 0x80, 0xF0, 0x80, 0x80, // F
         ];
 
-        for i in 0..80 {
-            chip8.memory[i] = font_set[i];
-        }
+        // SUPER-CHIP 8x10 digits, A - F added by XO-CHIP
+        let big_font_set: [u8; 160] = [
+            0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+            0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+            0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+            0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+            0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+            0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+            0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+            0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+        ];
+
+        chip8.memory[..80].copy_from_slice(&font_set);
+        chip8.memory[BIG_FONT_START..BIG_FONT_START + 160].copy_from_slice(&big_font_set);
 
         chip8
     }
 
-    pub fn load(&mut self, bin: Vec<u8>) -> Result<(), io::Error> {
+    pub fn load(&mut self, bin: &[u8], start: u16) -> Result<(), io::Error> {
         let length = bin.len();
-        let start_byte = PROGRAM_START;
-        let end_byte = PROGRAM_START + length;
-        if end_byte >= MEMORY_SIZE {
-            return Err(io::Error::new(io::ErrorKind::FileTooLarge, "binary too large."))
+        let start_byte = start as usize;
+        let end_byte = start_byte + length;
+        if end_byte > self.quirks.memory_size {
+            return Err(io::Error::new(
+                io::ErrorKind::FileTooLarge,
+                format!(
+                    "binary too large: {} bytes at 0x{:03X} exceeds {} bytes of memory",
+                    length, start_byte, self.quirks.memory_size
+                ),
+            ));
         }
 
-        self.memory[start_byte..end_byte].copy_from_slice(&bin);
+        self.memory[start_byte..end_byte].copy_from_slice(bin);
+        self.pc = start;
+
+        Ok(())
+    }
+
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.display
+    }
+
+    /// True when the display has changed since `clear_display_dirty` was last called.
+    pub fn display_dirty(&self) -> bool {
+        self.display_dirty
+    }
+
+    pub fn clear_display_dirty(&mut self) {
+        self.display_dirty = false;
+    }
+
+    /// Registers V0 -> VF
+    pub fn registers(&self) -> &[u8; NUM_REGISTERS] {
+        &self.registers
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    /// Delay and sound timers, in that order.
+    pub fn timers(&self) -> (u8, u8) {
+        (self.delay_timer, self.sound_timer)
+    }
+
+    /// XO-CHIP 1 bit audio pattern loaded by `AUDIO`, first sample in the top
+    /// bit. None until the ROM loads one.
+    pub fn audio_pattern(&self) -> Option<&[u8; AUDIO_PATTERN_SIZE]> {
+        self.audio_pattern_loaded.then_some(&self.audio_pattern)
+    }
 
-        return Ok(());
+    /// XO-CHIP playback rate of the audio pattern, see `audio::pattern_rate`.
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    /// Return addresses currently on the stack, oldest first.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.stack_pointer as usize]
+    }
+
+    /// The memory addressable under the current quirks.
+    pub fn memory(&self) -> &[u8] {
+        &self.memory[..self.quirks.memory_size]
+    }
+
+    /// Snapshot the whole machine into a versioned binary save state.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        write_quirks(&mut state, &self.quirks);
+        state.u32(MEMORY_SIZE as u32);
+        state.bytes(&self.memory);
+        state.bytes(&self.registers);
+        state.u16(self.index);
+        state.u16(self.pc);
+        for addr in self.stack {
+            state.u16(addr);
+        }
+        state.u8(self.stack_pointer);
+        state.u8(self.delay_timer);
+        state.u8(self.sound_timer);
+        for pressed in self.inputs {
+            state.bool(pressed);
+        }
+        state.bool(self.waiting_for_key);
+        // 0xFF for no key released
+        state.u8(self.released_key.unwrap_or(0xFF));
+        state.u16(self.display.width() as u16);
+        state.u16(self.display.height() as u16);
+        state.bytes(self.display.pixels());
+        state.u8(self.planes);
+        state.bytes(&self.rpl_flags);
+        state.bytes(&self.audio_pattern);
+        state.bool(self.audio_pattern_loaded);
+        state.u8(self.pitch);
+        state.bool(self.waiting_for_vblank);
+        state.u64(self.rng.state());
+        state.finish()
+    }
+
+    /// Restore a state from `save_state`. Nothing is changed if the state is invalid.
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), StateError> {
+        let mut state = StateReader::new(bytes)?;
+        // Running a state under other quirks would quietly change its behaviour
+        let quirks = read_quirks(&mut state)?;
+        if quirks != self.quirks {
+            return Err(StateError::QuirksMismatch(quirks.name()));
+        }
+        let mut chip8 = Chip8::with_seed(self.quirks, 0);
+        chip8.record_accesses = self.record_accesses;
+
+        if state.u32()? as usize != MEMORY_SIZE {
+            return Err(StateError::Invalid("memory size"));
+        }
+        chip8.memory.copy_from_slice(state.bytes(MEMORY_SIZE)?);
+        chip8.registers.copy_from_slice(state.bytes(NUM_REGISTERS)?);
+        chip8.index = state.u16()?;
+        chip8.pc = state.u16()?;
+        for addr in chip8.stack.iter_mut() {
+            *addr = state.u16()?;
+        }
+        chip8.stack_pointer = state.u8()?;
+        if chip8.stack_pointer as usize > STACK_SIZE {
+            return Err(StateError::Invalid("stack pointer"));
+        }
+        chip8.delay_timer = state.u8()?;
+        chip8.sound_timer = state.u8()?;
+        for pressed in chip8.inputs.iter_mut() {
+            *pressed = state.bool()?;
+        }
+        chip8.waiting_for_key = state.bool()?;
+        chip8.released_key = match state.u8()? {
+            0xFF => None,
+            key if (key as usize) < NUM_KEYS => Some(key),
+            _ => return Err(StateError::Invalid("released key")),
+        };
+        let width = state.u16()? as usize;
+        let height = state.u16()? as usize;
+        if (width, height) != (LORES_WIDTH, LORES_HEIGHT)
+            && (width, height) != (HIRES_WIDTH, HIRES_HEIGHT)
+        {
+            return Err(StateError::Invalid("display size"));
+        }
+        let pixels = state.bytes(width * height)?.to_vec();
+        chip8.display = Framebuffer::from_pixels(width, height, pixels)
+            .ok_or(StateError::Invalid("display"))?;
+        chip8.planes = state.u8()?;
+        if chip8.planes > 3 {
+            return Err(StateError::Invalid("planes"));
+        }
+        chip8.rpl_flags.copy_from_slice(state.bytes(NUM_RPL_FLAGS)?);
+        chip8
+            .audio_pattern
+            .copy_from_slice(state.bytes(AUDIO_PATTERN_SIZE)?);
+        chip8.audio_pattern_loaded = state.bool()?;
+        chip8.pitch = state.u8()?;
+        chip8.waiting_for_vblank = state.bool()?;
+        chip8.rng =
+            Rng::from_state(state.u64()?).ok_or(StateError::Invalid("random number generator"))?;
+        state.finish()?;
+
+        *self = chip8;
+        Ok(())
+    }
+
+    /// Turn recording of `memory_accesses` on or off, it is off by default.
+    pub fn record_memory_accesses(&mut self, enabled: bool) {
+        self.record_accesses = enabled;
+        self.memory_accesses.clear();
+    }
+
+    /// Data memory read or written by the last instruction executed.
+    pub fn memory_accesses(&self) -> &[MemoryAccess] {
+        &self.memory_accesses
+    }
+
+    /// Which of the 16 keypad keys are held down.
+    pub fn keys(&self) -> &[bool; NUM_KEYS] {
+        &self.inputs
+    }
+
+    /// Press or release one of the 16 keypad keys, 0x0 - 0xF.
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        let key = key & 0xF;
+        // LD Vx, K completes when a key is released, as on the COSMAC VIP
+        if self.waiting_for_key && !pressed && self.inputs[key as usize] {
+            self.released_key = Some(key);
+        }
+        self.inputs[key as usize] = pressed;
+    }
+
+    /// Run `cycles_per_frame` instructions followed by one 60hz timer tick.
+    pub fn run_frame(&mut self, cycles_per_frame: u32) -> Result<StepOutcome, Chip8Error> {
+        let mut outcome = StepOutcome::Executed;
+        for _ in 0..cycles_per_frame {
+            outcome = self.step()?;
+            if matches!(outcome, StepOutcome::WaitingForVblank | StepOutcome::Exit) {
+                break;
+            }
+        }
+        self.tick_timers();
+        Ok(outcome)
     }
 
-    pub fn emulate(&mut self) {
-        let opcode = ((self.memory[self.pc as usize] as u16) << 8)
-            | self.memory[(self.pc + 1) as usize] as u16;
+    /// Fetch and execute a single instruction.
+    pub fn step(&mut self) -> Result<StepOutcome, Chip8Error> {
+        if self.waiting_for_vblank {
+            return Ok(StepOutcome::WaitingForVblank);
+        }
 
-            println!("{}", opcode);
-        // TODO separate graphics print
-        self.print_graphics();
+        self.memory_accesses.clear();
+        let pc = self.pc;
+        if pc as usize + 1 >= self.quirks.memory_size {
+            return Err(Chip8Error::PcOutOfBounds { pc });
+        }
+        let opcode = ((self.memory[pc as usize] as u16) << 8) | self.memory[pc as usize + 1] as u16;
 
-        self.pc += 2;
-        self.execute(opcode);
+        let result = self.decode(pc, opcode).and_then(|instruction| {
+            self.pc = pc.wrapping_add(instruction.size() as u16);
+            self.execute(instruction)
+        });
+        let outcome = match result {
+            Ok(outcome) => outcome,
+            Err(fault) => {
+                // Leave pc on the faulting instruction so it can be inspected
+                self.pc = pc;
+                return Err(fault.at(pc, opcode));
+            }
+        };
+
+        Ok(outcome)
+    }
+
+    /// Count the delay and sound timers down, called at 60hz.
+    pub fn tick_timers(&mut self) {
+        self.waiting_for_vblank = false;
 
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
@@ -93,112 +415,215 @@ impl Chip8 {
 
         if self.sound_timer > 0 {
             self.sound_timer -= 1;
-            // Play sound whenever timer > 0. Probably just print ascii bell?
-        }
-    }
-
-    fn execute(&mut self, opcode: u16) {
-        let addr = opcode & 0x0FFF;
-        let x = ((opcode & 0x0F00) >> 8) as usize;
-        let y = ((opcode & 0x00F0) >> 4) as usize;
-        let byte = (opcode & 0x00FF) as u8;
-        let nibble = (opcode & 0x000F) as u8;
-
-        match opcode & 0xF000 {
-            0x0000 => match opcode {
-                0x00E0 /* CLS */ => self.display = [[false; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
-                0x00EE /* RET */ => self.return_subroutine(),
-                _ => println!("Invalid operation {:X}", opcode),
-            },
-            0x1000 /* JP addr */ => self.pc = addr,
-            0x2000 /* CALL addr */ => self.call_subroutine(addr),
-            0x3000 /* SE Vx, byte */ => self.skip_if_condition(self.registers[x] == byte),
-            0x4000 /* SNE Vx, byte */ => self.skip_if_condition(self.registers[x] != byte),
-            0x5000 /* SE Vx, Vy */ => self.skip_if_condition(self.registers[x] == self.registers[y]),
-            0x6000 /* LD Vx, byte */ => self.registers[x] = byte,
-            0x7000 /* ADD Vx, byte */ => self.registers[x] += byte,
-            0x8000 => match nibble {
-                0x0 /* LD Vx, Vy */ => self.registers[x] = self.registers[y],
-                0x1 /* OR Vx, Vy */ => self.registers[x] = self.registers[x] | self.registers[y],
-                0x2 /* AND Vx, Vy */ => self.registers[x] = self.registers[x] & self.registers[y],
-                0x3 /* XOR Vx, Vy */ => self.registers[x] = self.registers[x] ^ self.registers[y],
-                0x4 /* ADD Vx, Vy */ => self.add_registers(x, y),
-                0x5 /* SUB Vx, Vy */ => self.sub_registers(x, y),
-                0x6 /* SHR Vx */ => self.shift_right(x),
-                0x7 /* SUBN Vx */ => self.sub_registers_n(x, y),
-                0xE /* SHR Vx */ => self.shift_left(x),
-                _ => println!("Invalid operation {:X}", opcode),
-            },
-            0x9000 /* SNE Vx, Vy */ =>  self.skip_if_condition(self.registers[x] != self.registers[y]),
-            0xA000 /* LD I, addr */ => self.index = addr,
-            0xB000 /* JP V0, addr */ => self.pc = (addr + self.registers[0] as u16) & 0x3FFF,
-            0xC000 /* RND Vx, byte */ => self.registers[x] = rand::thread_rng().gen::<u8>() & byte,
-            0xD000 /* DRW Vx, Vy, nibble */ => self.draw_sprite(x, y, nibble as usize),
-            0xE000 => match byte {
-                0x9E /* SKP Vx */ => self.skip_if_condition(self.inputs[self.registers[x] as usize]),
-                0xA1 /* SKNP Vx */ => self.skip_if_condition(!self.inputs[self.registers[x] as usize]),
-                _ => println!("Invalid operation {:X}", opcode),
-            },
-            0xF000 => match byte {
-                0x07 /* LD Vx, DT */ => self.registers[x] = self.delay_timer,
-                0x0A /* LD Vx, K */ => self.wait_for_input(x),
-                0x15 /* LD DT, Vx */ => self.delay_timer = self.registers[x],
-                0x18 /* LD ST, Vx */ => self.sound_timer = self.registers[x],
-                0x1E /* ADD I, Vx */ => self.index += self.registers[x] as u16,
-                0x29 /* LD F, Vx */ => self.load_font(x),
-                0x33 /* LD B, Vx */ => self.store_binary_coded_decimal(x),
-                0x55 /* LD [I], Vx */ => self.store_many_registers(x),
-                0x65 /* LD Vx, [I] */ => self.load_many_registers(x),
-                _ => println!("Invalid operation {:X}", opcode),
-            },
-            _ => println!("Invalid operation {:X}", opcode),
-        }
-    }
-
-    fn print_graphics(&mut self) {
-        // TODO rather than print and clear each time, only print updated values/pixels
-        // Stops the flashing from ANSI printing
-
-        // Ansi clear screen
-        print!("{esc}[2J{esc}[1;1H", esc = 27 as char);
-
-        // Print screen
-        for row in self.display {
-            let chars = row.map(|enabled| if enabled { '█' } else { ' ' });
-            println!("{}", String::from_iter(chars));
-        }
-
-        // Print program counter and current op code next to screen
-        print!("{esc}[1;67HPC:0x{pc:0>3X}", esc = 27 as char, pc = self.pc);
-        let opcode = (self.memory[self.pc as usize] as u16) << 8
-            | self.memory[(self.pc + 1) as usize] as u16;
-        print!("{esc}[2;67HOp:0x{opcode:0>4X}", esc = 27 as char);
-        print!(
-            "{esc}[3;67HIndex:0x{index:0>4X}",
-            esc = 27 as char,
-            index = self.index
-        );
+        }
+    }
 
-        print!("{esc}[33;1H", esc = 27 as char);
-        io::stdout().flush().unwrap()
+    // Only the XO-CHIP long index load reads a second word
+    fn decode(&self, pc: u16, opcode: u16) -> Result<Instruction, Fault> {
+        let next = if opcode == 0xF000 {
+            Some(self.read_word(pc.wrapping_add(2))?)
+        } else {
+            None
+        };
+        Ok(Instruction::decode(opcode, next))
+    }
+
+    fn execute(&mut self, instruction: Instruction) -> Result<StepOutcome, Fault> {
+        match instruction {
+            Instruction::ScrollDown(n) => {
+                self.update_display(|display, planes| display.scroll_down(n as usize, planes))
+            }
+            Instruction::ScrollUp(n) => {
+                self.update_display(|display, planes| display.scroll_up(n as usize, planes))
+            }
+            Instruction::Clear => self.update_display(|display, planes| display.clear(planes)),
+            Instruction::Return => self.return_subroutine()?,
+            Instruction::ScrollRight => {
+                self.update_display(|display, planes| display.scroll_right(4, planes))
+            }
+            Instruction::ScrollLeft => {
+                self.update_display(|display, planes| display.scroll_left(4, planes))
+            }
+            Instruction::Exit => return Ok(self.exit()),
+            Instruction::LowRes => self.set_resolution(false),
+            Instruction::HighRes => self.set_resolution(true),
+            Instruction::Jump(addr) => self.pc = addr,
+            Instruction::Call(addr) => self.call_subroutine(addr)?,
+            Instruction::SkipEqImm { x, byte } => {
+                self.skip_if_condition(self.registers[x as usize] == byte)?
+            }
+            Instruction::SkipNeImm { x, byte } => {
+                self.skip_if_condition(self.registers[x as usize] != byte)?
+            }
+            Instruction::SkipEq { x, y } => {
+                self.skip_if_condition(self.registers[x as usize] == self.registers[y as usize])?
+            }
+            Instruction::SaveRange { x, y } => self.save_register_range(x as usize, y as usize)?,
+            Instruction::LoadRange { x, y } => self.load_register_range(x as usize, y as usize)?,
+            Instruction::LoadImm { x, byte } => self.registers[x as usize] = byte,
+            Instruction::AddImm { x, byte } => {
+                self.registers[x as usize] = self.registers[x as usize].wrapping_add(byte)
+            }
+            Instruction::Move { x, y } => self.registers[x as usize] = self.registers[y as usize],
+            Instruction::Or { x, y } => self.logic_registers(
+                x as usize,
+                self.registers[x as usize] | self.registers[y as usize],
+            ),
+            Instruction::And { x, y } => self.logic_registers(
+                x as usize,
+                self.registers[x as usize] & self.registers[y as usize],
+            ),
+            Instruction::Xor { x, y } => self.logic_registers(
+                x as usize,
+                self.registers[x as usize] ^ self.registers[y as usize],
+            ),
+            Instruction::Add { x, y } => self.add_registers(x as usize, y as usize),
+            Instruction::Sub { x, y } => self.sub_registers(x as usize, y as usize),
+            Instruction::ShiftRight { x, y } => self.shift_right(x as usize, y as usize),
+            Instruction::SubN { x, y } => self.sub_registers_n(x as usize, y as usize),
+            Instruction::ShiftLeft { x, y } => self.shift_left(x as usize, y as usize),
+            Instruction::SkipNe { x, y } => {
+                self.skip_if_condition(self.registers[x as usize] != self.registers[y as usize])?
+            }
+            Instruction::LoadIndex(addr) => self.index = addr,
+            Instruction::JumpOffset(addr) => self.jump_offset(addr),
+            Instruction::Random { x, byte } => {
+                self.registers[x as usize] = self.rng.next_u8() & byte
+            }
+            Instruction::Draw { x, y, n } => {
+                self.draw_sprite(x as usize, y as usize, n as usize)?
+            }
+            Instruction::SkipKey { x } => {
+                self.skip_if_condition(self.inputs[(self.registers[x as usize] & 0xF) as usize])?
+            }
+            Instruction::SkipNotKey { x } => {
+                self.skip_if_condition(!self.inputs[(self.registers[x as usize] & 0xF) as usize])?
+            }
+            Instruction::LongIndex(addr) => self.index = addr,
+            Instruction::Plane(n) => self.planes = n & 0x3,
+            Instruction::Audio => self.load_audio_pattern()?,
+            Instruction::LoadDelay { x } => self.registers[x as usize] = self.delay_timer,
+            Instruction::WaitKey { x } => return Ok(self.wait_for_input(x as usize)),
+            Instruction::SetDelay { x } => self.delay_timer = self.registers[x as usize],
+            Instruction::SetSound { x } => self.sound_timer = self.registers[x as usize],
+            Instruction::AddIndex { x } => {
+                self.index = self.index.wrapping_add(self.registers[x as usize] as u16)
+            }
+            Instruction::Font { x } => self.load_font(x as usize),
+            Instruction::BigFont { x } => self.load_big_font(x as usize),
+            Instruction::Bcd { x } => self.store_binary_coded_decimal(x as usize)?,
+            Instruction::Pitch { x } => self.pitch = self.registers[x as usize],
+            Instruction::Store { x } => self.store_many_registers(x as usize)?,
+            Instruction::Load { x } => self.load_many_registers(x as usize)?,
+            Instruction::StoreFlags { x } => {
+                self.rpl_flags[..=x as usize].copy_from_slice(&self.registers[..=x as usize])
+            }
+            Instruction::LoadFlags { x } => {
+                self.registers[..=x as usize].copy_from_slice(&self.rpl_flags[..=x as usize])
+            }
+            Instruction::Unknown(_) => return Err(Fault::InvalidOpcode),
+        }
+
+        Ok(StepOutcome::Executed)
     }
 
     // Op code functions
 
-    fn return_subroutine(&mut self) {
+    fn return_subroutine(&mut self) -> Result<(), Fault> {
+        if self.stack_pointer == 0 {
+            return Err(Fault::StackUnderflow);
+        }
         self.stack_pointer -= 1;
         self.pc = self.stack[self.stack_pointer as usize];
+        Ok(())
     }
 
-    fn call_subroutine(&mut self, addr: u16) {
+    fn call_subroutine(&mut self, addr: u16) -> Result<(), Fault> {
+        if self.stack_pointer as usize >= STACK_SIZE {
+            return Err(Fault::StackOverflow);
+        }
+        // Push the return address, pc already points past the call
+        self.stack[self.stack_pointer as usize] = self.pc;
         self.stack_pointer += 1;
-        self.stack[self.stack_pointer as usize] = addr
+        self.pc = addr;
+        Ok(())
     }
 
-    fn skip_if_condition(&mut self, con: bool) {
+    // Bounds check a run of `len` bytes starting at the index register,
+    // every data access goes through here so it can be recorded
+    fn index_range(
+        &mut self,
+        len: usize,
+        kind: AccessKind,
+    ) -> Result<std::ops::Range<usize>, Fault> {
+        let start = self.index as usize;
+        let memory_size = self.quirks.memory_size;
+        if start + len > memory_size {
+            return Err(Fault::MemoryOutOfBounds(memory_size.max(start)));
+        }
+        if self.record_accesses {
+            self.memory_accesses.push(MemoryAccess {
+                addr: start,
+                len,
+                kind,
+            });
+        }
+        Ok(start..start + len)
+    }
+
+    fn skip_if_condition(&mut self, con: bool) -> Result<(), Fault> {
         if con {
-            self.pc += 2
+            // Skip both words of an XO-CHIP long index load
+            let len = if self.read_word(self.pc)? == 0xF000 {
+                4
+            } else {
+                2
+            };
+            self.pc = self.pc.wrapping_add(len);
+        }
+        Ok(())
+    }
+
+    fn read_word(&self, addr: u16) -> Result<u16, Fault> {
+        let addr = addr as usize;
+        if addr + 1 >= self.quirks.memory_size {
+            return Err(Fault::MemoryOutOfBounds(addr + 1));
+        }
+        Ok(((self.memory[addr] as u16) << 8) | self.memory[addr + 1] as u16)
+    }
+
+    // Registers from x to y inclusive, in descending order when x > y
+    fn register_range(x: usize, y: usize) -> Vec<usize> {
+        if x <= y {
+            (x..=y).collect()
+        } else {
+            (y..=x).rev().collect()
+        }
+    }
+
+    fn save_register_range(&mut self, x: usize, y: usize) -> Result<(), Fault> {
+        let regs = Chip8::register_range(x, y);
+        let start = self.index_range(regs.len(), AccessKind::Write)?.start;
+        for (offset, reg_index) in regs.into_iter().enumerate() {
+            self.memory[start + offset] = self.registers[reg_index];
+        }
+        Ok(())
+    }
+
+    fn load_register_range(&mut self, x: usize, y: usize) -> Result<(), Fault> {
+        let regs = Chip8::register_range(x, y);
+        let start = self.index_range(regs.len(), AccessKind::Read)?.start;
+        for (offset, reg_index) in regs.into_iter().enumerate() {
+            self.registers[reg_index] = self.memory[start + offset];
         }
+        Ok(())
+    }
+
+    fn load_audio_pattern(&mut self) -> Result<(), Fault> {
+        let pattern = self.index_range(AUDIO_PATTERN_SIZE, AccessKind::Read)?;
+        self.audio_pattern.copy_from_slice(&self.memory[pattern]);
+        self.audio_pattern_loaded = true;
+        Ok(())
     }
 
     fn add_registers(&mut self, x: usize, y: usize) {
@@ -207,90 +632,785 @@ impl Chip8 {
         self.registers[0xF] = if carry { 1 } else { 0 };
     }
 
+    // VF is written last so it holds the flag even when it is Vx
     fn sub_registers(&mut self, x: usize, y: usize) {
-        self.registers[0xF] = if self.registers[x] >= self.registers[y] {
-            1
+        let (difference, borrow) = self.registers[x].overflowing_sub(self.registers[y]);
+        self.registers[x] = difference;
+        self.registers[0xF] = if borrow { 0 } else { 1 };
+    }
+
+    fn logic_registers(&mut self, x: usize, result: u8) {
+        self.registers[x] = result;
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
+        }
+    }
+
+    fn shift_right(&mut self, x: usize, y: usize) {
+        let val = if self.quirks.shifting {
+            self.registers[x]
         } else {
-            0
+            self.registers[y]
         };
-        self.registers[x] -= self.registers[y];
+        self.registers[x] = val >> 1;
+        self.registers[0xF] = val & 0x1;
     }
 
-    fn shift_right(&mut self, x: usize) {
-        self.registers[0xF] = self.registers[x] & 0x1;
-        self.registers[x] >>= 1;
+    fn sub_registers_n(&mut self, x: usize, y: usize) {
+        let (difference, borrow) = self.registers[y].overflowing_sub(self.registers[x]);
+        self.registers[x] = difference;
+        self.registers[0xF] = if borrow { 0 } else { 1 };
     }
 
-    fn sub_registers_n(&mut self, x: usize, y: usize) {
-        self.registers[0xF] = if self.registers[y] >= self.registers[x] {
-            1
+    fn shift_left(&mut self, x: usize, y: usize) {
+        let val = if self.quirks.shifting {
+            self.registers[x]
+        } else {
+            self.registers[y]
+        };
+        self.registers[x] = val << 1;
+        self.registers[0xF] = (val & 0x80) >> 7;
+    }
+
+    fn jump_offset(&mut self, addr: u16) {
+        // SUPER-CHIP reads Vx where x is the top nibble of the address
+        let x = (addr >> 8) as usize;
+        let offset = if self.quirks.jumping {
+            self.registers[x]
         } else {
-            0
+            self.registers[0]
         };
-        self.registers[x] = self.registers[y] - self.registers[x];
+        self.pc = (addr + offset as u16) & 0x3FFF;
+    }
+
+    fn exit(&mut self) -> StepOutcome {
+        // Stay on the exit instruction so further steps keep exiting
+        self.pc = self.pc.wrapping_sub(2);
+        StepOutcome::Exit
     }
 
-    fn shift_left(&mut self, x: usize) {
-        self.registers[0xF] = (self.registers[x] & 0x80) >> 7;
-        self.registers[x] <<= 1;
+    fn update_display<F: FnOnce(&mut Framebuffer, u8)>(&mut self, f: F) {
+        f(&mut self.display, self.planes);
+        self.display_dirty = true;
     }
 
-    fn draw_sprite(&mut self, x_reg: usize, y_reg: usize, n: usize) {
+    fn set_resolution(&mut self, hires: bool) {
+        self.display_dirty = true;
+        self.display = if hires {
+            Framebuffer::new(HIRES_WIDTH, HIRES_HEIGHT)
+        } else {
+            Framebuffer::new(LORES_WIDTH, LORES_HEIGHT)
+        };
+    }
+
+    // A height of 0 draws a SUPER-CHIP 16x16 sprite. With several XO-CHIP
+    // planes selected the sprite data for each plane follows on from the last.
+    fn draw_sprite(&mut self, x_reg: usize, y_reg: usize, n: usize) -> Result<(), Fault> {
+        let (width, height) = if n == 0 { (16, 16) } else { (8, n) };
+        let row_bytes = width / 8;
+        let sprite_len = height * row_bytes;
+        let planes: Vec<u8> = [1, 2]
+            .into_iter()
+            .filter(|plane| self.planes & plane != 0)
+            .collect();
+        let sprite = self.index_range(sprite_len * planes.len(), AccessKind::Read)?;
         let mut carry = false;
-        let x_coord = self.registers[x_reg] as usize;
-        let y_coord = self.registers[y_reg] as usize;
-
-        for y_offset in 0..n {
-            let src = self.memory[self.index as usize + y_offset];
-            for x_offset in 0..8 {
-                if (src & (0x80 >> x_offset)) != 0 {
-                    let y = (y_coord + y_offset) % DISPLAY_HEIGHT;
-                    let x = (x_coord + x_offset) % DISPLAY_WIDTH;
-
-                    if !carry && self.display[y][x] {
-                        carry = true;
+        let display_width = self.display.width();
+        let display_height = self.display.height();
+        // The starting position always wraps, the quirk decides what happens past the edge
+        let x_coord = self.registers[x_reg] as usize % display_width;
+        let y_coord = self.registers[y_reg] as usize % display_height;
+        let clipping = self.quirks.clipping;
+
+        for (plane_index, &plane) in planes.iter().enumerate() {
+            let plane_start = sprite.start + plane_index * sprite_len;
+            for y_offset in 0..height {
+                if clipping && y_coord + y_offset >= display_height {
+                    break;
+                }
+                let row_start = plane_start + y_offset * row_bytes;
+                let src = self.memory[row_start..row_start + row_bytes]
+                    .iter()
+                    .fold(0u16, |bits, &byte| (bits << 8) | byte as u16);
+                for x_offset in 0..width {
+                    if clipping && x_coord + x_offset >= display_width {
+                        break;
+                    }
+                    if (src & (1 << (width - 1 - x_offset))) != 0 {
+                        let y = (y_coord + y_offset) % display_height;
+                        let x = (x_coord + x_offset) % display_width;
+                        carry |= self.display.toggle(x, y, plane);
                     }
-                    self.display[y][x] ^= true;
                 }
             }
         }
         self.registers[0xF] = if carry { 1 } else { 0 };
+        self.display_dirty = true;
+        self.waiting_for_vblank = self.quirks.display_wait;
+        Ok(())
     }
 
-    fn wait_for_input(&mut self, x: usize) {
-        let mut pressed = false;
-        for index in 0..NUM_KEYS {
-            if self.inputs[index] {
-                self.registers[x] = index as u8;
-                pressed = true;
-                break;
-            }
+    fn wait_for_input(&mut self, x: usize) -> StepOutcome {
+        if !self.waiting_for_key {
+            self.waiting_for_key = true;
+            self.released_key = None;
         }
-        if !pressed {
-            self.pc -= 2;
+        if let Some(key) = self.released_key.take() {
+            self.waiting_for_key = false;
+            self.registers[x] = key;
+            return StepOutcome::Executed;
         }
+        self.pc = self.pc.wrapping_sub(2);
+        StepOutcome::WaitingForKey
     }
 
     fn load_font(&mut self, x: usize) {
         self.index = (self.registers[x] as u16) * 5;
     }
 
-    fn store_binary_coded_decimal(&mut self, x: usize) {
+    fn load_big_font(&mut self, x: usize) {
+        self.index = (BIG_FONT_START + (self.registers[x] & 0xF) as usize * 10) as u16;
+    }
+
+    fn store_binary_coded_decimal(&mut self, x: usize) -> Result<(), Fault> {
+        let start = self.index_range(3, AccessKind::Write)?.start;
         let val = self.registers[x];
-        self.memory[self.index as usize] = val / 100;
-        self.memory[(self.index + 1) as usize] = (val % 100) / 10;
-        self.memory[(self.index + 2) as usize] = val % 10;
+        self.memory[start] = val / 100;
+        self.memory[start + 1] = (val % 100) / 10;
+        self.memory[start + 2] = val % 10;
+        Ok(())
+    }
+
+    fn store_many_registers(&mut self, x: usize) -> Result<(), Fault> {
+        let start = self.index_range(x + 1, AccessKind::Write)?.start;
+        for reg_index in 0..=x {
+            self.memory[start + reg_index] = self.registers[reg_index];
+        }
+        self.increment_index(x);
+        Ok(())
+    }
+
+    fn load_many_registers(&mut self, x: usize) -> Result<(), Fault> {
+        let start = self.index_range(x + 1, AccessKind::Read)?.start;
+        for reg_index in 0..=x {
+            self.registers[reg_index] = self.memory[start + reg_index]
+        }
+        self.increment_index(x);
+        Ok(())
+    }
+
+    fn increment_index(&mut self, x: usize) {
+        match self.quirks.memory {
+            IndexIncrement::Unchanged => {}
+            IndexIncrement::ByX => self.index = self.index.wrapping_add(x as u16),
+            IndexIncrement::ByXPlusOne => self.index = self.index.wrapping_add(x as u16 + 1),
+        }
+    }
+}
+
+fn write_quirks(state: &mut StateWriter, quirks: &Quirks) {
+    state.bool(quirks.vf_reset);
+    state.u8(quirks.memory as u8);
+    state.bool(quirks.display_wait);
+    state.bool(quirks.clipping);
+    state.bool(quirks.shifting);
+    state.bool(quirks.jumping);
+    state.u32(quirks.memory_size as u32);
+}
+
+fn read_quirks(state: &mut StateReader) -> Result<Quirks, StateError> {
+    Ok(Quirks {
+        vf_reset: state.bool()?,
+        memory: match state.u8()? {
+            0 => IndexIncrement::Unchanged,
+            1 => IndexIncrement::ByX,
+            2 => IndexIncrement::ByXPlusOne,
+            _ => return Err(StateError::Invalid("memory quirk")),
+        },
+        display_wait: state.bool()?,
+        clipping: state.bool()?,
+        shifting: state.bool()?,
+        jumping: state.bool()?,
+        memory_size: match state.u32()? as usize {
+            size if size <= MEMORY_SIZE => size,
+            _ => return Err(StateError::Invalid("memory size")),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine(quirks: Quirks) -> Chip8 {
+        Chip8::with_seed(quirks, 0)
+    }
+
+    // Put `opcode` at pc and execute it
+    fn exec(chip8: &mut Chip8, opcode: u16) -> Result<StepOutcome, Chip8Error> {
+        let pc = chip8.pc as usize;
+        chip8.memory[pc..pc + 2].copy_from_slice(&opcode.to_be_bytes());
+        chip8.step()
+    }
+
+    fn pixel(chip8: &Chip8, x: usize, y: usize) -> u8 {
+        chip8.display.pixels()[y * chip8.display.width() + x]
+    }
+
+    #[test]
+    fn clear_00e0() {
+        let mut chip8 = machine(Quirks::xochip());
+        chip8.display.toggle(3, 4, 1);
+        exec(&mut chip8, 0x00E0).unwrap();
+        assert!(chip8.display.pixels().iter().all(|&pixel| pixel == 0));
+        assert!(chip8.display_dirty());
+    }
+
+    #[test]
+    fn scroll_00cn_00dn_00fb_00fc() {
+        let mut chip8 = machine(Quirks::xochip());
+        chip8.display.toggle(10, 10, 1);
+        exec(&mut chip8, 0x00C3).unwrap();
+        assert_eq!(pixel(&chip8, 10, 13), 1);
+        exec(&mut chip8, 0x00D2).unwrap();
+        assert_eq!(pixel(&chip8, 10, 11), 1);
+        exec(&mut chip8, 0x00FB).unwrap();
+        assert_eq!(pixel(&chip8, 14, 11), 1);
+        exec(&mut chip8, 0x00FC).unwrap();
+        assert_eq!(pixel(&chip8, 10, 11), 1);
+        assert_eq!(
+            chip8
+                .display
+                .pixels()
+                .iter()
+                .filter(|&&pixel| pixel != 0)
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn call_2nnn_and_return_00ee() {
+        let mut chip8 = machine(Quirks::chip8());
+        exec(&mut chip8, 0x2400).unwrap();
+        assert_eq!(chip8.pc(), 0x400);
+        assert_eq!(chip8.stack(), &[0x202]);
+        exec(&mut chip8, 0x00EE).unwrap();
+        assert_eq!(chip8.pc(), 0x202);
+        assert!(chip8.stack().is_empty());
+    }
+
+    #[test]
+    fn return_00ee_with_empty_stack_underflows() {
+        let mut chip8 = machine(Quirks::chip8());
+        assert_eq!(
+            exec(&mut chip8, 0x00EE),
+            Err(Chip8Error::StackUnderflow {
+                pc: 0x200,
+                opcode: 0x00EE
+            })
+        );
+    }
+
+    #[test]
+    fn call_2nnn_overflows_full_stack() {
+        let mut chip8 = machine(Quirks::chip8());
+        // Each call jumps to itself, pushing its own address
+        chip8.memory[0x200..0x202].copy_from_slice(&[0x22, 0x00]);
+        for _ in 0..STACK_SIZE {
+            chip8.step().unwrap();
+        }
+        assert_eq!(
+            chip8.step(),
+            Err(Chip8Error::StackOverflow {
+                pc: 0x200,
+                opcode: 0x2200
+            })
+        );
+    }
+
+    #[test]
+    fn exit_00fd_stays_put() {
+        let mut chip8 = machine(Quirks::superchip());
+        assert_eq!(exec(&mut chip8, 0x00FD), Ok(StepOutcome::Exit));
+        assert_eq!(chip8.pc(), 0x200);
+        assert_eq!(chip8.step(), Ok(StepOutcome::Exit));
+    }
+
+    #[test]
+    fn resolution_00fe_00ff() {
+        let mut chip8 = machine(Quirks::superchip());
+        exec(&mut chip8, 0x00FF).unwrap();
+        assert_eq!(
+            (chip8.display.width(), chip8.display.height()),
+            (HIRES_WIDTH, HIRES_HEIGHT)
+        );
+        exec(&mut chip8, 0x00FE).unwrap();
+        assert_eq!(
+            (chip8.display.width(), chip8.display.height()),
+            (LORES_WIDTH, LORES_HEIGHT)
+        );
+    }
+
+    #[test]
+    fn jump_1nnn() {
+        let mut chip8 = machine(Quirks::chip8());
+        exec(&mut chip8, 0x1ABC).unwrap();
+        assert_eq!(chip8.pc(), 0xABC);
+    }
+
+    #[test]
+    fn skips_3xkk_4xkk_5xy0_9xy0() {
+        let cases = [
+            (0x3133, true),
+            (0x3134, false),
+            (0x4133, false),
+            (0x4134, true),
+            (0x5120, true),
+            (0x5130, false),
+            (0x9120, false),
+            (0x9130, true),
+        ];
+        for (opcode, skips) in cases {
+            let mut chip8 = machine(Quirks::chip8());
+            chip8.registers[1] = 0x33;
+            chip8.registers[2] = 0x33;
+            chip8.registers[3] = 0x34;
+            exec(&mut chip8, opcode).unwrap();
+            assert_eq!(
+                chip8.pc(),
+                if skips { 0x204 } else { 0x202 },
+                "{:04X}",
+                opcode
+            );
+        }
+    }
+
+    #[test]
+    fn skip_steps_over_long_index_load() {
+        let mut chip8 = machine(Quirks::xochip());
+        chip8.memory[0x202..0x206].copy_from_slice(&[0xF0, 0x00, 0x12, 0x34]);
+        exec(&mut chip8, 0x3000).unwrap();
+        assert_eq!(chip8.pc(), 0x206);
+    }
+
+    #[test]
+    fn save_5xy2_and_load_5xy3_ranges() {
+        let mut chip8 = machine(Quirks::xochip());
+        chip8.registers[1..4].copy_from_slice(&[1, 2, 3]);
+        chip8.index = 0x300;
+        exec(&mut chip8, 0x5132).unwrap();
+        assert_eq!(&chip8.memory[0x300..0x304], &[1, 2, 3, 0]);
+        // Descending when x > y, I is left alone
+        exec(&mut chip8, 0x5312).unwrap();
+        assert_eq!(&chip8.memory[0x300..0x303], &[3, 2, 1]);
+        assert_eq!(chip8.index(), 0x300);
+
+        chip8.memory[0x300..0x303].copy_from_slice(&[7, 8, 9]);
+        exec(&mut chip8, 0x5463).unwrap();
+        assert_eq!(&chip8.registers[4..7], &[7, 8, 9]);
+    }
+
+    #[test]
+    fn load_6xkk_and_add_7xkk_wraps_without_flag() {
+        let mut chip8 = machine(Quirks::chip8());
+        exec(&mut chip8, 0x61F0).unwrap();
+        exec(&mut chip8, 0x7120).unwrap();
+        assert_eq!(chip8.registers[1], 0x10);
+        assert_eq!(chip8.registers[0xF], 0);
+    }
+
+    #[test]
+    fn move_8xy0() {
+        let mut chip8 = machine(Quirks::chip8());
+        chip8.registers[2] = 0x42;
+        exec(&mut chip8, 0x8120).unwrap();
+        assert_eq!(chip8.registers[1], 0x42);
+    }
+
+    #[test]
+    fn logic_8xy1_8xy2_8xy3() {
+        for (opcode, result) in [(0x8121, 0b1110), (0x8122, 0b1000), (0x8123, 0b0110)] {
+            for (quirks, vf) in [(Quirks::chip8(), 0), (Quirks::xochip(), 1)] {
+                let mut chip8 = machine(quirks);
+                chip8.registers[1] = 0b1100;
+                chip8.registers[2] = 0b1010;
+                chip8.registers[0xF] = 1;
+                exec(&mut chip8, opcode).unwrap();
+                assert_eq!(chip8.registers[1], result, "{:04X}", opcode);
+                // Only reset under the vf_reset quirk
+                assert_eq!(chip8.registers[0xF], vf, "{:04X}", opcode);
+            }
+        }
+    }
+
+    #[test]
+    fn add_8xy4_sets_carry() {
+        let mut chip8 = machine(Quirks::chip8());
+        chip8.registers[1] = 0xF0;
+        chip8.registers[2] = 0x20;
+        exec(&mut chip8, 0x8124).unwrap();
+        assert_eq!((chip8.registers[1], chip8.registers[0xF]), (0x10, 1));
+        exec(&mut chip8, 0x8124).unwrap();
+        assert_eq!((chip8.registers[1], chip8.registers[0xF]), (0x30, 0));
+    }
+
+    #[test]
+    fn sub_8xy5_sets_not_borrow() {
+        let mut chip8 = machine(Quirks::chip8());
+        chip8.registers[1] = 0x10;
+        chip8.registers[2] = 0x20;
+        exec(&mut chip8, 0x8125).unwrap();
+        assert_eq!((chip8.registers[1], chip8.registers[0xF]), (0xF0, 0));
+        chip8.registers[2] = 0xF0;
+        exec(&mut chip8, 0x8125).unwrap();
+        assert_eq!((chip8.registers[1], chip8.registers[0xF]), (0x00, 1));
+    }
+
+    #[test]
+    fn subn_8xy7_sets_not_borrow() {
+        let mut chip8 = machine(Quirks::chip8());
+        chip8.registers[1] = 0x20;
+        chip8.registers[2] = 0x10;
+        exec(&mut chip8, 0x8127).unwrap();
+        assert_eq!((chip8.registers[1], chip8.registers[0xF]), (0xF0, 0));
+        chip8.registers[1] = 0x05;
+        exec(&mut chip8, 0x8127).unwrap();
+        assert_eq!((chip8.registers[1], chip8.registers[0xF]), (0x0B, 1));
+    }
+
+    #[test]
+    fn arithmetic_into_vf_keeps_the_flag() {
+        for (opcode, vf) in [
+            (0x8F14, 1),
+            (0x8F15, 0),
+            (0x8F17, 1),
+            (0x8F16, 0),
+            (0x8F1E, 1),
+        ] {
+            let mut chip8 = machine(Quirks::chip8());
+            chip8.registers[0xF] = 0x81;
+            chip8.registers[1] = 0x90;
+            exec(&mut chip8, opcode).unwrap();
+            assert_eq!(chip8.registers[0xF], vf, "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn shifts_8xy6_8xye() {
+        // Vy is shifted into Vx unless the shifting quirk is set
+        for (quirks, source) in [(Quirks::chip8(), 2), (Quirks::superchip(), 1)] {
+            let mut chip8 = machine(quirks);
+            chip8.registers[1] = 0b1000_0001;
+            chip8.registers[2] = 0b0100_0010;
+            let value = chip8.registers[source];
+            exec(&mut chip8, 0x8126).unwrap();
+            assert_eq!(
+                (chip8.registers[1], chip8.registers[0xF]),
+                (value >> 1, value & 1)
+            );
+
+            chip8.registers[1] = 0b1000_0001;
+            exec(&mut chip8, 0x812E).unwrap();
+            assert_eq!(
+                (chip8.registers[1], chip8.registers[0xF]),
+                (value << 1, value >> 7)
+            );
+        }
+    }
+
+    #[test]
+    fn load_index_annn() {
+        let mut chip8 = machine(Quirks::chip8());
+        exec(&mut chip8, 0xA123).unwrap();
+        assert_eq!(chip8.index(), 0x123);
     }
 
-    fn store_many_registers(&mut self, x: usize) {
-        for reg_index in 0..x {
-            self.memory[(self.index as usize) + reg_index] = self.registers[reg_index];
+    #[test]
+    fn jump_offset_bnnn() {
+        for (quirks, target) in [(Quirks::chip8(), 0x310), (Quirks::superchip(), 0x320)] {
+            let mut chip8 = machine(quirks);
+            chip8.registers[0] = 0x10;
+            chip8.registers[3] = 0x20;
+            exec(&mut chip8, 0xB300).unwrap();
+            assert_eq!(chip8.pc(), target);
         }
     }
 
-    fn load_many_registers(&mut self, x: usize) {
-        for reg_index in 0..x {
-            self.registers[reg_index] = self.memory[(self.index as usize) + reg_index]
+    #[test]
+    fn random_cxkk_is_masked_and_seeded() {
+        let mut a = machine(Quirks::chip8());
+        let mut b = machine(Quirks::chip8());
+        for _ in 0..32 {
+            exec(&mut a, 0xC10F).unwrap();
+            exec(&mut b, 0xC10F).unwrap();
+            assert_eq!(a.registers[1] & 0xF0, 0);
+            assert_eq!(a.registers[1], b.registers[1]);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn draw_dxyn_toggles_and_reports_collision() {
+        let mut chip8 = machine(Quirks::xochip());
+        chip8.memory[0x300] = 0b1100_0000;
+        chip8.index = 0x300;
+        chip8.registers[1] = 2;
+        chip8.registers[2] = 3;
+        exec(&mut chip8, 0xD121).unwrap();
+        assert_eq!((pixel(&chip8, 2, 3), pixel(&chip8, 3, 3)), (1, 1));
+        assert_eq!(chip8.registers[0xF], 0);
+        exec(&mut chip8, 0xD121).unwrap();
+        assert_eq!((pixel(&chip8, 2, 3), pixel(&chip8, 3, 3)), (0, 0));
+        assert_eq!(chip8.registers[0xF], 1);
+    }
+
+    #[test]
+    fn draw_dxyn_clips_or_wraps_at_the_edge() {
+        for (quirks, wrapped) in [(Quirks::superchip(), 0), (Quirks::xochip(), 1)] {
+            let mut chip8 = machine(quirks);
+            chip8.memory[0x300] = 0b1100_0000;
+            chip8.index = 0x300;
+            // The start position always wraps, 64 + 63 is 63
+            chip8.registers[1] = 127;
+            exec(&mut chip8, 0xD121).unwrap();
+            assert_eq!(pixel(&chip8, 63, 0), 1);
+            assert_eq!(pixel(&chip8, 0, 0), wrapped);
+        }
+    }
+
+    #[test]
+    fn draw_dxyn_waits_for_vblank_under_display_wait() {
+        let mut chip8 = machine(Quirks::chip8());
+        chip8.index = 0x300;
+        exec(&mut chip8, 0xD001).unwrap();
+        assert_eq!(chip8.step(), Ok(StepOutcome::WaitingForVblank));
+        chip8.tick_timers();
+        assert_eq!(chip8.pc(), 0x202);
+        assert_eq!(exec(&mut chip8, 0x6000), Ok(StepOutcome::Executed));
+    }
+
+    #[test]
+    fn draw_dxy0_draws_16x16_sprite() {
+        let mut chip8 = machine(Quirks::superchip());
+        exec(&mut chip8, 0x00FF).unwrap();
+        chip8.memory[0x300..0x320].fill(0xFF);
+        chip8.index = 0x300;
+        exec(&mut chip8, 0xD000).unwrap();
+        assert_eq!(
+            chip8
+                .display
+                .pixels()
+                .iter()
+                .filter(|&&pixel| pixel != 0)
+                .count(),
+            256
+        );
+        assert_eq!((pixel(&chip8, 15, 15), pixel(&chip8, 16, 16)), (1, 0));
+    }
+
+    #[test]
+    fn plane_fn01_selects_planes_for_draw() {
+        let mut chip8 = machine(Quirks::xochip());
+        // One row for plane 1, then one for plane 2
+        chip8.memory[0x300..0x302].copy_from_slice(&[0b1000_0000, 0b0100_0000]);
+        chip8.index = 0x300;
+        exec(&mut chip8, 0xF301).unwrap();
+        assert_eq!(chip8.planes, 3);
+        exec(&mut chip8, 0xD001).unwrap();
+        assert_eq!((pixel(&chip8, 0, 0), pixel(&chip8, 1, 0)), (1, 2));
+    }
+
+    #[test]
+    fn key_skips_ex9e_exa1() {
+        let mut chip8 = machine(Quirks::chip8());
+        chip8.registers[1] = 0xA;
+        chip8.set_key(0xA, true);
+        exec(&mut chip8, 0xE19E).unwrap();
+        assert_eq!(chip8.pc(), 0x204);
+        exec(&mut chip8, 0xE1A1).unwrap();
+        assert_eq!(chip8.pc(), 0x206);
+        chip8.set_key(0xA, false);
+        exec(&mut chip8, 0xE1A1).unwrap();
+        assert_eq!(chip8.pc(), 0x20A);
+    }
+
+    #[test]
+    fn long_index_f000() {
+        let mut chip8 = machine(Quirks::xochip());
+        chip8.memory[0x202..0x204].copy_from_slice(&[0xAB, 0xCD]);
+        exec(&mut chip8, 0xF000).unwrap();
+        assert_eq!((chip8.index(), chip8.pc()), (0xABCD, 0x204));
+    }
+
+    #[test]
+    fn audio_f002_and_pitch_fx3a() {
+        let mut chip8 = machine(Quirks::xochip());
+        for (i, byte) in chip8.memory[0x300..0x310].iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        chip8.index = 0x300;
+        assert_eq!(chip8.audio_pattern(), None);
+        exec(&mut chip8, 0xF002).unwrap();
+        assert_eq!(chip8.audio_pattern().unwrap()[15], 15);
+        // Still loaded after a save state round trip
+        let mut restored = machine(Quirks::xochip());
+        restored.load_state(&chip8.save_state()).unwrap();
+        assert_eq!(restored.audio_pattern(), chip8.audio_pattern());
+        chip8.registers[1] = 112;
+        exec(&mut chip8, 0xF13A).unwrap();
+        assert_eq!(chip8.pitch(), 112);
+    }
+
+    #[test]
+    fn timers_fx07_fx15_fx18() {
+        let mut chip8 = machine(Quirks::chip8());
+        chip8.registers[1] = 5;
+        exec(&mut chip8, 0xF115).unwrap();
+        exec(&mut chip8, 0xF118).unwrap();
+        chip8.tick_timers();
+        assert_eq!(chip8.timers(), (4, 4));
+        exec(&mut chip8, 0xF207).unwrap();
+        assert_eq!(chip8.registers[2], 4);
+    }
+
+    #[test]
+    fn wait_key_fx0a_completes_on_release() {
+        let mut chip8 = machine(Quirks::chip8());
+        assert_eq!(exec(&mut chip8, 0xF10A), Ok(StepOutcome::WaitingForKey));
+        chip8.set_key(7, true);
+        assert_eq!(chip8.step(), Ok(StepOutcome::WaitingForKey));
+        chip8.set_key(7, false);
+        assert_eq!(chip8.step(), Ok(StepOutcome::Executed));
+        assert_eq!((chip8.registers[1], chip8.pc()), (7, 0x202));
+    }
+
+    #[test]
+    fn add_index_fx1e_wraps() {
+        let mut chip8 = machine(Quirks::xochip());
+        chip8.index = 0xFFFF;
+        chip8.registers[1] = 2;
+        exec(&mut chip8, 0xF11E).unwrap();
+        assert_eq!(chip8.index(), 1);
+        assert_eq!(chip8.registers[0xF], 0);
+    }
+
+    #[test]
+    fn fonts_fx29_fx30() {
+        let mut chip8 = machine(Quirks::superchip());
+        chip8.registers[1] = 0xA;
+        exec(&mut chip8, 0xF129).unwrap();
+        assert_eq!(chip8.index(), 50);
+        exec(&mut chip8, 0xF130).unwrap();
+        assert_eq!(chip8.index(), BIG_FONT_START as u16 + 100);
+    }
+
+    #[test]
+    fn bcd_fx33() {
+        let mut chip8 = machine(Quirks::chip8());
+        chip8.registers[1] = 254;
+        chip8.index = 0x300;
+        exec(&mut chip8, 0xF133).unwrap();
+        assert_eq!(&chip8.memory[0x300..0x303], &[2, 5, 4]);
+    }
+
+    #[test]
+    fn store_fx55_and_load_fx65_include_vx() {
+        let quirks = [
+            (Quirks::chip8(), 0x303),
+            (Quirks::chip48(), 0x302),
+            (Quirks::superchip(), 0x300),
+        ];
+        for (quirks, index) in quirks {
+            let mut chip8 = machine(quirks);
+            chip8.registers[..4].copy_from_slice(&[1, 2, 3, 4]);
+            chip8.index = 0x300;
+            exec(&mut chip8, 0xF255).unwrap();
+            assert_eq!(&chip8.memory[0x300..0x304], &[1, 2, 3, 0]);
+            assert_eq!(chip8.index(), index);
+
+            chip8.registers = [0; NUM_REGISTERS];
+            chip8.index = 0x300;
+            exec(&mut chip8, 0xF265).unwrap();
+            assert_eq!(&chip8.registers[..4], &[1, 2, 3, 0]);
+            assert_eq!(chip8.index(), index);
+        }
+    }
+
+    #[test]
+    fn flags_fx75_fx85() {
+        let mut chip8 = machine(Quirks::superchip());
+        chip8.registers[..3].copy_from_slice(&[7, 8, 9]);
+        exec(&mut chip8, 0xF175).unwrap();
+        chip8.registers = [0; NUM_REGISTERS];
+        exec(&mut chip8, 0xF285).unwrap();
+        assert_eq!(&chip8.registers[..3], &[7, 8, 0]);
+    }
+
+    #[test]
+    fn invalid_opcode_faults() {
+        let mut chip8 = machine(Quirks::chip8());
+        assert_eq!(
+            exec(&mut chip8, 0x5121),
+            Err(Chip8Error::InvalidOpcode {
+                pc: 0x200,
+                opcode: 0x5121
+            })
+        );
+    }
+
+    #[test]
+    fn save_state_under_other_quirks_is_rejected() {
+        let state = machine(Quirks::xochip()).save_state();
+        let mut chip8 = machine(Quirks::chip8());
+        assert_eq!(
+            chip8.load_state(&state),
+            Err(StateError::QuirksMismatch(Some("xochip")))
+        );
+        assert!(machine(Quirks::xochip()).load_state(&state).is_ok());
+    }
+
+    #[test]
+    fn save_state_with_bad_planes_is_rejected() {
+        let mut chip8 = machine(Quirks::xochip());
+        chip8.planes = 4;
+        let state = chip8.save_state();
+        assert_eq!(
+            machine(Quirks::xochip()).load_state(&state),
+            Err(StateError::Invalid("planes"))
+        );
+    }
+
+    #[test]
+    fn memory_past_4k_faults_before_xo_chip() {
+        let mut chip8 = machine(Quirks::superchip());
+        chip8.index = 0xFFE;
+        let fault = exec(&mut chip8, 0xF255);
+        assert!(matches!(
+            fault,
+            Err(Chip8Error::MemoryOutOfBounds { addr: 0x1000, .. })
+        ));
+        assert_eq!(chip8.memory().len(), 0x1000);
+        assert!(chip8.load(&[0; 2], 0xFFF).is_err());
+
+        let mut chip8 = machine(Quirks::xochip());
+        chip8.index = 0xFFE;
+        assert_eq!(exec(&mut chip8, 0xF255), Ok(StepOutcome::Executed));
+    }
+
+    #[test]
+    fn index_past_memory_faults() {
+        let mut chip8 = machine(Quirks::xochip());
+        chip8.index = 0xFFFE;
+        let fault = exec(&mut chip8, 0xF255);
+        assert!(matches!(
+            fault,
+            Err(Chip8Error::MemoryOutOfBounds {
+                pc: 0x200,
+                opcode: 0xF255,
+                ..
+            })
+        ));
+        // Nothing is written when the access faults
+        assert_eq!(chip8.memory[0xFFFE], 0);
+    }
+}
@@ -0,0 +1,604 @@
+use crate::render::Theme;
+use chip8emu::chip::PROGRAM_START;
+use chip8emu::screenshot::Format;
+use chip8emu::trace::TraceFilter;
+use chip8emu::Quirks;
+use std::fmt;
+use std::path::Path;
+
+pub const USAGE: &str = "\
+Usage: chip8emu [run] [OPTIONS] <ROM>
+       chip8emu disasm [--start <ADDR>] <ROM>
+       chip8emu asm [--start <ADDR>] [-o <OUT>] <SOURCE>
+       chip8emu compare [--quirks <PROFILE>] [--cycles-per-frame <N>] [--start <ADDR>] [--seed <N>]
+                        [--keys <SCRIPT>] <ROM> <LOG>
+
+Commands:
+  run                   Run the ROM in the terminal (default)
+  disasm                Print addresses, raw bytes and mnemonics, unreachable bytes as data
+  asm                   Assemble Cowgod style source into a ROM, written to <SOURCE>.ch8 by default
+  compare               Run the ROM against another emulator's state log, stopping at the
+                        first difference
+
+Options:
+  --clock <HZ>          Instructions executed per second, rounded to whole frames (default 600)
+  --cycles-per-frame <N>
+                        Instructions executed per 60hz frame (default 10)
+  --quirks <PROFILE>    Quirk profile: chip8, chip48, schip, xochip (default chip8)
+  --scale <N>           Terminal columns drawn per pixel (default 1)
+  --renderer <NAME>     Renderer: block (two rows per line), full, ascii, none (default block)
+  --theme <NAME>        Colours: mono, green, amber, octo (default mono)
+  --rewind <SECONDS>    Seconds of history kept for rewinding with backspace, 0 to disable
+                        (default 30)
+  --debug               Start paused in the step debugger, backtick breaks back into it
+  --start <ADDR>        Address the ROM is loaded and started at (default 0x200)
+  --seed <N>            Seed for RND so runs can be repeated exactly (default random, 0 for
+                        compare)
+  --record <FILE>       Record the keys held each frame to a movie file, written on exit
+  --play <FILE>         Replay a movie, its quirks, seed, speed and start address replace
+                        the options
+  --audio               Play sound through aplay
+  --wav <FILE>          Write sound to a WAV file instead
+  --beep <HZ>           Frequency of the sound timer's square wave, unless an XO-CHIP ROM loads an
+                        audio pattern (default 440)
+  --volume <PERCENT>    Sound volume from 0 to 100 (default 25)
+  --headless            Run without a terminal for --frames frames, then print the display's hash
+  --frames <N>          Frames to run headless
+  --keys <SCRIPT>       Key presses for a headless run, one '<frame> <key> down|up' per line
+  --screenshot <FILE>   Save the display after a headless run as .png or .pbm
+  --trace <FILE>        Write a line per instruction executed to FILE
+  --trace-pc <START-END>
+                        Only trace instructions at these addresses, e.g. 0x200-0x2FF
+  --trace-cycles <START-END>
+                        Only trace these instructions counted from 0, e.g. 1000-2000 or 1000-
+  -h, --help            Print this help";
+
+pub const FRAME_RATE: f64 = 60.0;
+
+/// Where sound goes, nowhere unless asked for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AudioOutput {
+    Aplay,
+    Wav(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RendererKind {
+    Block,
+    Full,
+    Ascii,
+    None,
+}
+
+/// Subcommand to run with its options.
+#[derive(Debug)]
+pub enum Command {
+    Run(Box<Options>),
+    Disasm {
+        rom: String,
+        start: u16,
+    },
+    Asm {
+        source: String,
+        output: String,
+        start: u16,
+    },
+    Compare(CompareOptions),
+}
+
+#[derive(Debug)]
+pub struct CompareOptions {
+    pub rom: String,
+    pub log: String,
+    pub keys: Option<String>,
+    pub quirks: Quirks,
+    pub cycles_per_frame: u32,
+    pub start: u16,
+    pub seed: u64,
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub rom: String,
+    pub cycles_per_frame: u32,
+    pub quirks: Quirks,
+    pub scale: usize,
+    pub renderer: RendererKind,
+    pub theme: Theme,
+    pub start: u16,
+    pub rewind_seconds: u32,
+    pub debug: bool,
+    pub seed: Option<u64>,
+    pub record: Option<String>,
+    pub play: Option<String>,
+    pub headless: bool,
+    pub frames: Option<u64>,
+    pub keys: Option<String>,
+    pub screenshot: Option<(String, Format)>,
+    pub audio: Option<AudioOutput>,
+    pub beep_frequency: f64,
+    pub volume: f64,
+    pub trace: Option<String>,
+    pub trace_filter: TraceFilter,
+}
+
+#[derive(Debug)]
+pub enum CliError {
+    Help,
+    MissingRom,
+    MissingSource,
+    MissingLog,
+    MissingValue(String),
+    InvalidValue(String, String),
+    UnknownOption(String),
+    Conflict(&'static str, &'static str),
+    Requires(&'static str, &'static str),
+    UnexpectedArgument(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CliError::Help => write!(f, "{}", USAGE),
+            CliError::MissingRom => write!(f, "no ROM path given"),
+            CliError::MissingSource => write!(f, "no source file given"),
+            CliError::MissingLog => write!(f, "no log file given"),
+            CliError::MissingValue(opt) => write!(f, "{} needs a value", opt),
+            CliError::InvalidValue(opt, val) => write!(f, "invalid value '{}' for {}", val, opt),
+            CliError::UnknownOption(opt) => write!(f, "unknown option '{}'", opt),
+            CliError::Conflict(a, b) => write!(f, "{} cannot be used with {}", a, b),
+            CliError::Requires(a, b) => write!(f, "{} needs {}", a, b),
+            CliError::UnexpectedArgument(arg) => write!(f, "unexpected argument '{}'", arg),
+        }
+    }
+}
+
+pub fn parse<I: Iterator<Item = String>>(args: I) -> Result<Command, CliError> {
+    let mut args = args.peekable();
+    match args.peek().map(String::as_str) {
+        Some("disasm") => {
+            args.next();
+            parse_disasm(args)
+        }
+        Some("asm") => {
+            args.next();
+            parse_asm(args)
+        }
+        Some("compare") => {
+            args.next();
+            parse_compare(args)
+        }
+        Some("run") => {
+            args.next();
+            parse_run(args).map(|options| Command::Run(Box::new(options)))
+        }
+        _ => parse_run(args).map(|options| Command::Run(Box::new(options))),
+    }
+}
+
+fn parse_disasm<I: Iterator<Item = String>>(mut args: I) -> Result<Command, CliError> {
+    let mut rom = None;
+    let mut start = PROGRAM_START;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Err(CliError::Help),
+            "--start" => {
+                let val = value(&mut args, &arg)?;
+                start = match parse_number(&val) {
+                    Some(addr) => addr,
+                    None => return Err(CliError::InvalidValue(arg, val)),
+                };
+            }
+            _ if arg.starts_with('-') => return Err(CliError::UnknownOption(arg)),
+            _ if rom.is_none() => rom = Some(arg),
+            _ => return Err(CliError::UnexpectedArgument(arg)),
+        }
+    }
+    let rom = rom.ok_or(CliError::MissingRom)?;
+    Ok(Command::Disasm { rom, start })
+}
+
+fn parse_asm<I: Iterator<Item = String>>(mut args: I) -> Result<Command, CliError> {
+    let mut source = None;
+    let mut output = None;
+    let mut start = PROGRAM_START;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Err(CliError::Help),
+            "-o" | "--output" => output = Some(value(&mut args, &arg)?),
+            "--start" => {
+                let val = value(&mut args, &arg)?;
+                start = match parse_number(&val) {
+                    Some(addr) => addr,
+                    None => return Err(CliError::InvalidValue(arg, val)),
+                };
+            }
+            _ if arg.starts_with('-') => return Err(CliError::UnknownOption(arg)),
+            _ if source.is_none() => source = Some(arg),
+            _ => return Err(CliError::UnexpectedArgument(arg)),
+        }
+    }
+    let source = source.ok_or(CliError::MissingSource)?;
+    // game.asm becomes game.ch8
+    let output = output.unwrap_or_else(|| {
+        Path::new(&source)
+            .with_extension("ch8")
+            .to_string_lossy()
+            .into_owned()
+    });
+    Ok(Command::Asm {
+        source,
+        output,
+        start,
+    })
+}
+
+fn parse_compare<I: Iterator<Item = String>>(mut args: I) -> Result<Command, CliError> {
+    let mut files = Vec::new();
+    let mut options = CompareOptions {
+        rom: String::new(),
+        log: String::new(),
+        keys: None,
+        quirks: Quirks::chip8(),
+        cycles_per_frame: 10,
+        start: PROGRAM_START,
+        seed: 0,
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Err(CliError::Help),
+            "--keys" => options.keys = Some(value(&mut args, &arg)?),
+            "--seed" => options.seed = parse_seed(&mut args, arg)?,
+            "--quirks" => {
+                let val = value(&mut args, &arg)?;
+                options.quirks = match Quirks::from_name(&val) {
+                    Some(quirks) => quirks,
+                    None => return Err(CliError::InvalidValue(arg, val)),
+                };
+            }
+            "--cycles-per-frame" => {
+                let val = value(&mut args, &arg)?;
+                options.cycles_per_frame = match val.parse::<u32>() {
+                    Ok(cycles) if cycles > 0 => cycles,
+                    _ => return Err(CliError::InvalidValue(arg, val)),
+                };
+            }
+            "--start" => {
+                let val = value(&mut args, &arg)?;
+                options.start = match parse_number(&val) {
+                    Some(addr) => addr,
+                    None => return Err(CliError::InvalidValue(arg, val)),
+                };
+            }
+            _ if arg.starts_with('-') => return Err(CliError::UnknownOption(arg)),
+            _ if files.len() < 2 => files.push(arg),
+            _ => return Err(CliError::UnexpectedArgument(arg)),
+        }
+    }
+    let mut files = files.into_iter();
+    options.rom = files.next().ok_or(CliError::MissingRom)?;
+    options.log = files.next().ok_or(CliError::MissingLog)?;
+    Ok(Command::Compare(options))
+}
+
+fn parse_run<I: Iterator<Item = String>>(mut args: I) -> Result<Options, CliError> {
+    let mut rom = None;
+    let mut options = Options {
+        rom: String::new(),
+        cycles_per_frame: 10,
+        quirks: Quirks::chip8(),
+        scale: 1,
+        renderer: RendererKind::Block,
+        theme: Theme::from_name("mono").unwrap(),
+        start: PROGRAM_START,
+        rewind_seconds: 30,
+        debug: false,
+        seed: None,
+        record: None,
+        play: None,
+        headless: false,
+        frames: None,
+        keys: None,
+        screenshot: None,
+        audio: None,
+        beep_frequency: 440.0,
+        volume: 0.25,
+        trace: None,
+        trace_filter: TraceFilter::default(),
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Err(CliError::Help),
+            "--clock" => {
+                let val = value(&mut args, &arg)?;
+                options.cycles_per_frame = match val.parse::<f64>() {
+                    Ok(hz) if hz >= FRAME_RATE => (hz / FRAME_RATE).round() as u32,
+                    _ => return Err(CliError::InvalidValue(arg, val)),
+                };
+            }
+            "--cycles-per-frame" => {
+                let val = value(&mut args, &arg)?;
+                options.cycles_per_frame = match val.parse::<u32>() {
+                    Ok(cycles) if cycles > 0 => cycles,
+                    _ => return Err(CliError::InvalidValue(arg, val)),
+                };
+            }
+            "--quirks" => {
+                let val = value(&mut args, &arg)?;
+                options.quirks = match Quirks::from_name(&val) {
+                    Some(quirks) => quirks,
+                    None => return Err(CliError::InvalidValue(arg, val)),
+                };
+            }
+            "--scale" => {
+                let val = value(&mut args, &arg)?;
+                options.scale = match val.parse::<usize>() {
+                    Ok(scale) if scale > 0 => scale,
+                    _ => return Err(CliError::InvalidValue(arg, val)),
+                };
+            }
+            "--renderer" => {
+                let val = value(&mut args, &arg)?;
+                options.renderer = match val.as_str() {
+                    "block" => RendererKind::Block,
+                    "full" => RendererKind::Full,
+                    "ascii" => RendererKind::Ascii,
+                    "none" => RendererKind::None,
+                    _ => return Err(CliError::InvalidValue(arg, val)),
+                };
+            }
+            "--theme" => {
+                let val = value(&mut args, &arg)?;
+                options.theme = match Theme::from_name(&val) {
+                    Some(theme) => theme,
+                    None => return Err(CliError::InvalidValue(arg, val)),
+                };
+            }
+            "--debug" => options.debug = true,
+            "--rewind" => {
+                let val = value(&mut args, &arg)?;
+                options.rewind_seconds = match val.parse::<u32>() {
+                    Ok(seconds) => seconds,
+                    _ => return Err(CliError::InvalidValue(arg, val)),
+                };
+            }
+            "--start" => {
+                let val = value(&mut args, &arg)?;
+                options.start = match parse_number(&val) {
+                    Some(addr) => addr,
+                    None => return Err(CliError::InvalidValue(arg, val)),
+                };
+            }
+            "--seed" => options.seed = Some(parse_seed(&mut args, arg)?),
+            "--record" => options.record = Some(value(&mut args, &arg)?),
+            "--play" => options.play = Some(value(&mut args, &arg)?),
+            "--headless" => options.headless = true,
+            "--frames" => {
+                let val = value(&mut args, &arg)?;
+                options.frames = match val.parse::<u64>() {
+                    Ok(frames) => Some(frames),
+                    _ => return Err(CliError::InvalidValue(arg, val)),
+                };
+            }
+            "--keys" => options.keys = Some(value(&mut args, &arg)?),
+            "--screenshot" => {
+                let val = value(&mut args, &arg)?;
+                options.screenshot = match Format::from_path(&val) {
+                    Some(format) => Some((val, format)),
+                    None => return Err(CliError::InvalidValue(arg, val)),
+                };
+            }
+            "--audio" | "--wav" => {
+                let output = match arg.as_str() {
+                    "--audio" => AudioOutput::Aplay,
+                    _ => AudioOutput::Wav(value(&mut args, &arg)?),
+                };
+                if options.audio.as_ref().is_some_and(|audio| *audio != output) {
+                    return Err(CliError::Conflict("--audio", "--wav"));
+                }
+                options.audio = Some(output);
+            }
+            "--beep" => {
+                let val = value(&mut args, &arg)?;
+                options.beep_frequency = match val.parse::<f64>() {
+                    Ok(hz) if hz > 0.0 && hz < 20_000.0 => hz,
+                    _ => return Err(CliError::InvalidValue(arg, val)),
+                };
+            }
+            "--volume" => {
+                let val = value(&mut args, &arg)?;
+                options.volume = match val.parse::<u32>() {
+                    Ok(percent) if percent <= 100 => percent as f64 / 100.0,
+                    _ => return Err(CliError::InvalidValue(arg, val)),
+                };
+            }
+            "--trace" => options.trace = Some(value(&mut args, &arg)?),
+            "--trace-pc" => {
+                let val = value(&mut args, &arg)?;
+                options.trace_filter.addresses = match val.split_once('-') {
+                    Some((start, end)) => match (parse_number(start), parse_number(end)) {
+                        (Some(start), Some(end)) if start <= end => Some(start..=end),
+                        _ => return Err(CliError::InvalidValue(arg, val)),
+                    },
+                    None => return Err(CliError::InvalidValue(arg, val)),
+                };
+            }
+            "--trace-cycles" => {
+                let val = value(&mut args, &arg)?;
+                // Inclusive of END, or unbounded when it is left off
+                options.trace_filter.cycles = match val.split_once('-') {
+                    Some((start, "")) => start.parse().ok().map(|start| start..u64::MAX),
+                    Some((start, end)) => match (start.parse::<u64>(), end.parse::<u64>()) {
+                        (Ok(start), Ok(end)) if start <= end => Some(start..end.saturating_add(1)),
+                        _ => None,
+                    },
+                    None => None,
+                };
+                if options.trace_filter.cycles.is_none() {
+                    return Err(CliError::InvalidValue(arg, val));
+                }
+            }
+            _ if arg.starts_with('-') => return Err(CliError::UnknownOption(arg)),
+            _ if rom.is_none() => rom = Some(arg),
+            _ => return Err(CliError::UnexpectedArgument(arg)),
+        }
+    }
+
+    if options.record.is_some() && options.play.is_some() {
+        return Err(CliError::Conflict("--record", "--play"));
+    }
+    if options.keys.is_some() && options.play.is_some() {
+        return Err(CliError::Conflict("--keys", "--play"));
+    }
+    if options.debug && options.record.is_some() {
+        return Err(CliError::Conflict("--record", "--debug"));
+    }
+    if options.debug && options.play.is_some() {
+        return Err(CliError::Conflict("--play", "--debug"));
+    }
+    if options.headless {
+        if options.debug {
+            return Err(CliError::Conflict("--headless", "--debug"));
+        }
+        if options.frames.is_none() {
+            return Err(CliError::Requires("--headless", "--frames"));
+        }
+    } else if options.frames.is_some() {
+        return Err(CliError::Requires("--frames", "--headless"));
+    } else if options.keys.is_some() {
+        return Err(CliError::Requires("--keys", "--headless"));
+    } else if options.screenshot.is_some() {
+        return Err(CliError::Requires("--screenshot", "--headless"));
+    }
+    options.rom = rom.ok_or(CliError::MissingRom)?;
+    Ok(options)
+}
+
+fn value<I: Iterator<Item = String>>(args: &mut I, opt: &str) -> Result<String, CliError> {
+    args.next()
+        .ok_or_else(|| CliError::MissingValue(opt.to_string()))
+}
+
+// Any 64 bit number, in decimal or 0x prefixed hex
+fn parse_seed<I: Iterator<Item = String>>(args: &mut I, arg: String) -> Result<u64, CliError> {
+    let val = value(args, &arg)?;
+    let seed = match val.strip_prefix("0x").or_else(|| val.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => val.parse().ok(),
+    };
+    seed.ok_or(CliError::InvalidValue(arg, val))
+}
+
+// Accepts decimal or 0x prefixed hex
+fn parse_number(val: &str) -> Option<u16> {
+    match val.strip_prefix("0x").or_else(|| val.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => val.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &str) -> Result<Command, CliError> {
+        parse(args.split_whitespace().map(String::from))
+    }
+
+    fn run_options(args: &str) -> Options {
+        match parse_args(args) {
+            Ok(Command::Run(options)) => *options,
+            other => panic!("{}: {:?}", args, other),
+        }
+    }
+
+    #[test]
+    fn run_is_the_default_command() {
+        let options = run_options("--quirks schip --clock 1200 --start 0x300 game.ch8");
+        assert_eq!(options.rom, "game.ch8");
+        assert_eq!(options.quirks, Quirks::superchip());
+        assert_eq!(options.cycles_per_frame, 20);
+        assert_eq!(options.start, 0x300);
+        assert_eq!(run_options("run game.ch8").rom, "game.ch8");
+    }
+
+    #[test]
+    fn other_commands() {
+        assert!(matches!(
+            parse_args("disasm --start 0x200 game.ch8"),
+            Ok(Command::Disasm { rom, start: 0x200 }) if rom == "game.ch8"
+        ));
+        assert!(matches!(
+            parse_args("asm game.asm"),
+            Ok(Command::Asm { output, .. }) if output == "game.ch8"
+        ));
+        assert!(matches!(
+            parse_args("compare game.ch8 game.log"),
+            Ok(Command::Compare(CompareOptions { seed: 0, .. }))
+        ));
+    }
+
+    #[test]
+    fn bad_values_and_arguments() {
+        assert!(matches!(parse_args(""), Err(CliError::MissingRom)));
+        assert!(matches!(
+            parse_args("game.ch8 --quirks"),
+            Err(CliError::MissingValue(opt)) if opt == "--quirks"
+        ));
+        assert!(matches!(
+            parse_args("--quirks vip game.ch8"),
+            Err(CliError::InvalidValue(opt, val)) if opt == "--quirks" && val == "vip"
+        ));
+        // A value with no option before it is taken as a second ROM
+        assert!(matches!(
+            parse_args("game.ch8 600"),
+            Err(CliError::UnexpectedArgument(arg)) if arg == "600"
+        ));
+        assert!(matches!(
+            parse_args("--fast game.ch8"),
+            Err(CliError::UnknownOption(opt)) if opt == "--fast"
+        ));
+        assert!(matches!(parse_args("--help"), Err(CliError::Help)));
+    }
+
+    #[test]
+    fn conflicting_and_incomplete_options() {
+        let cases = [
+            ("--headless game.ch8", "--headless needs --frames"),
+            ("--frames 10 game.ch8", "--frames needs --headless"),
+            (
+                "--play a.movie --record b.movie game.ch8",
+                "--record cannot be used with --play",
+            ),
+            (
+                "--debug --record a.movie game.ch8",
+                "--record cannot be used with --debug",
+            ),
+            (
+                "--headless --frames 10 --debug game.ch8",
+                "--headless cannot be used with --debug",
+            ),
+            (
+                "--audio --wav out.wav game.ch8",
+                "--audio cannot be used with --wav",
+            ),
+        ];
+        for (args, message) in cases {
+            match parse_args(args) {
+                Err(err) => assert_eq!(err.to_string(), message, "{}", args),
+                Ok(_) => panic!("{} was accepted", args),
+            }
+        }
+    }
+
+    #[test]
+    fn trace_ranges() {
+        let options = run_options("--trace-pc 0x200-0x2FF --trace-cycles 100- game.ch8");
+        assert_eq!(options.trace_filter.addresses, Some(0x200..=0x2FF));
+        assert_eq!(options.trace_filter.cycles, Some(100..u64::MAX));
+        let options = run_options("--trace-cycles 5-9 game.ch8");
+        assert_eq!(options.trace_filter.cycles, Some(5..10));
+        assert!(parse_args("--trace-pc 0x300-0x200 game.ch8").is_err());
+    }
+}
@@ -0,0 +1,393 @@
+//! Run a ROM against a state log from another emulator and find the first
+//! instruction where the two disagree.
+//!
+//! The log has a line for every instruction executed, describing the
+//! machine after it ran. Spinning on `LD Vx, K` while no key is released
+//! does not count. Each line is a set of space separated `key=value` fields
+//! in hex, and any field can be left out to skip comparing it:
+//!
+//! ```text
+//! # cycle=<n> pc=<addr> v=<V0..VF as 32 hex digits> i=<addr> fb=<hash>
+//! cycle=2 pc=0206 v=05030000000000000000000000000000 i=0300 fb=8D4E7D45
+//! ```
+//!
+//! `cycle` is decimal and only used in reports. `fb` is the 32 bit FNV-1a
+//! hash of the display, one byte per pixel row by row from the top left
+//! holding a bit per XO-CHIP plane. Blank lines and `#` comments are ignored,
+//! as are unknown fields.
+
+use crate::chip::{Chip8, StepOutcome};
+use crate::instruction::Instruction;
+use crate::keyscript::KeyScript;
+use std::fmt;
+
+/// Machine state from one line of a log, None for fields it leaves out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogState {
+    pub cycle: Option<u64>,
+    pub pc: Option<u16>,
+    pub registers: Option<[u8; 16]>,
+    pub index: Option<u16>,
+    pub framebuffer: Option<u32>,
+}
+
+impl LogState {
+    /// Parse a line of a log, None for blank lines and comments.
+    pub fn parse(line: &str) -> Result<Option<Self>, String> {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            return Ok(None);
+        }
+        let mut state = LogState::default();
+        for field in line.split_whitespace() {
+            let (key, value) = field
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got '{}'", field))?;
+            let error = || format!("bad value for {}: '{}'", key, value);
+            match key {
+                "cycle" => state.cycle = Some(value.parse().map_err(|_| error())?),
+                "pc" => state.pc = Some(u16::from_str_radix(value, 16).map_err(|_| error())?),
+                "i" => state.index = Some(u16::from_str_radix(value, 16).map_err(|_| error())?),
+                "fb" => {
+                    state.framebuffer = Some(u32::from_str_radix(value, 16).map_err(|_| error())?)
+                }
+                "v" => {
+                    if value.len() != 32 || !value.is_ascii() {
+                        return Err(error());
+                    }
+                    let mut registers = [0; 16];
+                    for (reg, pair) in registers.iter_mut().zip(value.as_bytes().chunks(2)) {
+                        let pair = std::str::from_utf8(pair).map_err(|_| error())?;
+                        *reg = u8::from_str_radix(pair, 16).map_err(|_| error())?;
+                    }
+                    state.registers = Some(registers);
+                }
+                _ => {}
+            }
+        }
+        Ok(Some(state))
+    }
+
+    /// Every field of `chip8`'s current state.
+    pub fn capture(chip8: &Chip8, cycle: u64) -> Self {
+        LogState {
+            cycle: Some(cycle),
+            pc: Some(chip8.pc()),
+            registers: Some(*chip8.registers()),
+            index: Some(chip8.index()),
+            framebuffer: Some(chip8.framebuffer().hash()),
+        }
+    }
+
+    // Fields set in `self` that `actual` disagrees with
+    fn mismatches(&self, actual: &LogState) -> Vec<&'static str> {
+        let mut fields = Vec::new();
+        if self.pc.is_some() && self.pc != actual.pc {
+            fields.push("pc");
+        }
+        if self.registers.is_some() && self.registers != actual.registers {
+            fields.push("v");
+        }
+        if self.index.is_some() && self.index != actual.index {
+            fields.push("i");
+        }
+        if self.framebuffer.is_some() && self.framebuffer != actual.framebuffer {
+            fields.push("fb");
+        }
+        fields
+    }
+
+    // Display rows for the side by side report, "-" for missing fields
+    fn rows(&self) -> Vec<(String, String)> {
+        let show = |value: Option<String>| value.unwrap_or_else(|| String::from("-"));
+        let mut rows = vec![
+            (
+                String::from("pc"),
+                show(self.pc.map(|pc| format!("{:04X}", pc))),
+            ),
+            (
+                String::from("i"),
+                show(self.index.map(|index| format!("{:04X}", index))),
+            ),
+            (
+                String::from("fb"),
+                show(self.framebuffer.map(|hash| format!("{:08X}", hash))),
+            ),
+        ];
+        for reg in 0..16 {
+            let value = self
+                .registers
+                .map(|registers| format!("{:02X}", registers[reg]));
+            rows.push((format!("V{:X}", reg), show(value)));
+        }
+        rows
+    }
+}
+
+impl fmt::Display for LogState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut fields = Vec::new();
+        if let Some(cycle) = self.cycle {
+            fields.push(format!("cycle={}", cycle));
+        }
+        if let Some(pc) = self.pc {
+            fields.push(format!("pc={:04X}", pc));
+        }
+        if let Some(registers) = self.registers {
+            fields.push(format!(
+                "v={}",
+                registers
+                    .iter()
+                    .map(|reg| format!("{:02X}", reg))
+                    .collect::<String>()
+            ));
+        }
+        if let Some(index) = self.index {
+            fields.push(format!("i={:04X}", index));
+        }
+        if let Some(hash) = self.framebuffer {
+            fields.push(format!("fb={:08X}", hash));
+        }
+        write!(f, "{}", fields.join(" "))
+    }
+}
+
+/// First point where the emulator and the log disagree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Line of the log, counted from 1.
+    pub line: usize,
+    pub cycle: u64,
+    /// The instruction that was just executed and where.
+    pub executed: Option<(u16, Instruction)>,
+    pub expected: LogState,
+    pub actual: LogState,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cycle = self.expected.cycle.unwrap_or(self.cycle);
+        writeln!(f, "diverged at line {} (cycle {})", self.line, cycle)?;
+        if let Some((pc, instruction)) = self.executed {
+            writeln!(f, "after executing {} at 0x{:03X}", instruction, pc)?;
+        }
+        writeln!(f, "{:<6}{:<12}actual", "", "expected")?;
+        // Registers are only listed when one of them differs
+        let show_registers = self.expected.mismatches(&self.actual).contains(&"v");
+        for ((name, expected), (_, actual)) in
+            self.expected.rows().into_iter().zip(self.actual.rows())
+        {
+            if name.starts_with('V') && !show_registers {
+                continue;
+            }
+            let marker = if expected != "-" && expected != actual {
+                "<--"
+            } else {
+                ""
+            };
+            let row = format!("{:<6}{:<12}{:<12}{}", name, expected, actual, marker);
+            writeln!(f, "{}", row.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+/// How a comparison ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Comparison {
+    /// Every line of the log matched.
+    Matched {
+        lines: usize,
+    },
+    Diverged(Divergence),
+    /// The emulator faulted or exited with lines of the log left.
+    Stopped {
+        line: usize,
+        cycle: u64,
+        reason: String,
+    },
+}
+
+/// Run `chip8` frame by frame with `keys`, checking the state after every
+/// instruction against the next line of `log`. Errors are log parse errors.
+pub fn compare(
+    chip8: &mut Chip8,
+    log: &str,
+    keys: &KeyScript,
+    cycles_per_frame: u32,
+) -> Result<Comparison, String> {
+    let mut expected = Vec::new();
+    for (i, line) in log.lines().enumerate() {
+        if let Some(state) =
+            LogState::parse(line).map_err(|err| format!("line {}: {}", i + 1, err))?
+        {
+            expected.push((i + 1, state));
+        }
+    }
+
+    let mut lines = expected.into_iter().peekable();
+    let mut cycle = 0;
+    let mut frame = 0;
+    while lines.peek().is_some() {
+        keys.apply(frame, chip8);
+        let mut waiting_for_key = false;
+        for _ in 0..cycles_per_frame {
+            let Some(&(line, _)) = lines.peek() else {
+                break;
+            };
+            let pc = chip8.pc();
+            let instruction = Instruction::decode_at(chip8.memory(), pc as usize);
+            let outcome = match chip8.step() {
+                Ok(outcome) => outcome,
+                Err(err) => {
+                    return Ok(Comparison::Stopped {
+                        line,
+                        cycle,
+                        reason: err.to_string(),
+                    })
+                }
+            };
+            waiting_for_key = outcome == StepOutcome::WaitingForKey;
+            match outcome {
+                StepOutcome::WaitingForVblank => break,
+                StepOutcome::WaitingForKey => continue,
+                StepOutcome::Exit => {
+                    return Ok(Comparison::Stopped {
+                        line,
+                        cycle,
+                        reason: String::from("program exited"),
+                    })
+                }
+                StepOutcome::Executed => {}
+            }
+            let (line, state) = lines.next().unwrap();
+            let actual = LogState::capture(chip8, cycle);
+            if !state.mismatches(&actual).is_empty() {
+                let executed = instruction.map(|instruction| (pc, instruction));
+                return Ok(Comparison::Diverged(Divergence {
+                    line,
+                    cycle,
+                    executed,
+                    expected: state,
+                    actual,
+                }));
+            }
+            cycle += 1;
+        }
+        // Nothing left in the script could release a key
+        if waiting_for_key && keys.events().iter().all(|event| event.frame <= frame) {
+            let line = lines.peek().map_or(0, |&(line, _)| line);
+            return Ok(Comparison::Stopped {
+                line,
+                cycle,
+                reason: String::from("waiting for a key"),
+            });
+        }
+        chip8.tick_timers();
+        frame += 1;
+    }
+    Ok(Comparison::Matched {
+        lines: cycle as usize,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+
+    // LD V0, 5; LD V1, 3; ADD V0, V1; LD I, 0x300; JP 0x208
+    const ROM: [u8; 10] = [0x60, 0x05, 0x61, 0x03, 0x80, 0x14, 0xA3, 0x00, 0x12, 0x08];
+
+    fn machine() -> Chip8 {
+        let mut chip8 = Chip8::with_seed(Quirks::chip8(), 0);
+        chip8.load(&ROM, 0x200).unwrap();
+        chip8
+    }
+
+    // What this emulator would log for `cycles` instructions of ROM
+    fn log(cycles: u64) -> Vec<String> {
+        let mut chip8 = machine();
+        (0..cycles)
+            .map(|cycle| {
+                chip8.step().unwrap();
+                LogState::capture(&chip8, cycle).to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parse_fields() {
+        let state = LogState::parse("cycle=2 pc=0206 i=0300 fb=8D4E7D45 extra=1")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            state,
+            LogState {
+                cycle: Some(2),
+                pc: Some(0x206),
+                registers: None,
+                index: Some(0x300),
+                framebuffer: Some(0x8D4E7D45),
+            }
+        );
+        assert_eq!(LogState::parse("  # just a comment"), Ok(None));
+        assert_eq!(LogState::parse(""), Ok(None));
+        assert!(LogState::parse("pc").is_err());
+        assert!(LogState::parse("pc=xyz").is_err());
+    }
+
+    #[test]
+    fn parse_registers() {
+        let state = LogState::parse("v=0503000000000000000000000000FF01")
+            .unwrap()
+            .unwrap();
+        let mut registers = [0; 16];
+        registers[..2].copy_from_slice(&[0x05, 0x03]);
+        registers[14..].copy_from_slice(&[0xFF, 0x01]);
+        assert_eq!(state.registers, Some(registers));
+        assert_eq!(state.to_string(), "v=0503000000000000000000000000FF01");
+
+        for bad in [
+            "v=0503",
+            "v=0503000000000000000000000000FF0G",
+            "v=é503000000000000000000000000FF0",
+        ] {
+            assert!(LogState::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn own_log_matches() {
+        let log = log(6).join("\n");
+        let mut chip8 = machine();
+        let keys = KeyScript::parse("").unwrap();
+        assert_eq!(
+            compare(&mut chip8, &log, &keys, 10),
+            Ok(Comparison::Matched { lines: 6 })
+        );
+    }
+
+    #[test]
+    fn finds_a_planted_divergence() {
+        let mut lines = log(6);
+        lines[2] = lines[2].replace("v=0803", "v=0903");
+        let mut chip8 = machine();
+        let keys = KeyScript::parse("").unwrap();
+        let Ok(Comparison::Diverged(divergence)) =
+            compare(&mut chip8, &lines.join("\n"), &keys, 10)
+        else {
+            panic!("no divergence found");
+        };
+        assert_eq!(divergence.line, 3);
+        assert_eq!(divergence.cycle, 2);
+        assert_eq!(
+            divergence.executed,
+            Some((0x204, Instruction::Add { x: 0, y: 1 }))
+        );
+        assert_eq!(divergence.expected.mismatches(&divergence.actual), ["v"]);
+        assert!(divergence
+            .to_string()
+            .contains("V0    09          08          <--"));
+    }
+}
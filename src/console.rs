@@ -0,0 +1,90 @@
+use crate::render::Renderer;
+use crate::terminal::RawTerminal;
+use chip8emu::debugger::{self, Command, Debugger, StepFn, StopReason};
+use chip8emu::Chip8;
+use std::io::{self, BufRead, Write};
+
+const ESC: char = 27 as char;
+
+/// What the emulator should do once the console hands back control.
+pub enum Resume {
+    Run,
+    Quit,
+}
+
+/// Interactive debugger prompt shown below the screen while paused.
+/// `output` is shown first, such as the reason execution stopped, and
+/// `step_fn` executes each instruction stepped through.
+pub fn prompt(
+    chip8: &mut Chip8,
+    debugger: &mut Debugger,
+    step_fn: &mut StepFn,
+    terminal: &mut RawTerminal,
+    renderer: &mut dyn Renderer,
+    mut output: String,
+) -> io::Result<Resume> {
+    terminal.set_raw(false)?;
+    let resume = loop {
+        // Redraw in full, anything printed last time may have scrolled the screen
+        renderer.reset();
+        renderer.render(chip8)?;
+        chip8.clear_display_dirty();
+        print!("{}[{};1H{}[J{}[?25h", ESC, renderer.lines() + 2, ESC, ESC);
+        if !output.is_empty() {
+            println!("{}", output);
+        }
+        print!("(debug) ");
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        if io::stdin().lock().read_line(&mut line)? == 0 {
+            break Resume::Quit;
+        }
+        output = match Command::parse(&line) {
+            Ok(Command::Step(count)) => step(chip8, debugger, step_fn, count),
+            Ok(Command::Continue) => break Resume::Run,
+            Ok(Command::RunTo(addr)) => {
+                debugger.run_to(addr);
+                break Resume::Run;
+            }
+            Ok(Command::Break(breakpoint)) => {
+                debugger.add(breakpoint);
+                format!("#{} {}", debugger.breakpoints().len() - 1, breakpoint)
+            }
+            Ok(Command::Delete(index)) => match debugger.delete(index) {
+                Some(breakpoint) => format!("deleted {}", breakpoint),
+                None => format!("no breakpoint #{}", index),
+            },
+            Ok(Command::List) if debugger.breakpoints().is_empty() => {
+                String::from("no breakpoints")
+            }
+            Ok(Command::List) => debugger
+                .breakpoints()
+                .iter()
+                .enumerate()
+                .map(|(i, breakpoint)| format!("#{} {}", i, breakpoint))
+                .collect::<Vec<String>>()
+                .join("\n"),
+            Ok(Command::Registers) => debugger::format_registers(chip8),
+            Ok(Command::Stack) => debugger::format_stack(chip8),
+            Ok(Command::Timers) => debugger::format_timers(chip8),
+            Ok(Command::Dump { addr, len }) => debugger::hexdump(chip8, addr, len),
+            Ok(Command::Help) => String::from(debugger::HELP),
+            Ok(Command::Quit) => break Resume::Quit,
+            Err(err) => err,
+        };
+    };
+    terminal.set_raw(true)?;
+    Ok(resume)
+}
+
+fn step(chip8: &mut Chip8, debugger: &mut Debugger, step_fn: &mut StepFn, count: u32) -> String {
+    let mut reason = StopReason::Step;
+    for _ in 0..count {
+        if let Err(stop) = debugger.step_with(chip8, step_fn) {
+            reason = stop;
+            break;
+        }
+    }
+    format!("{}\n{}", reason, debugger::format_registers(chip8))
+}
@@ -0,0 +1,602 @@
+use crate::chip::{AccessKind, Chip8, StepOutcome};
+use crate::error::Chip8Error;
+use crate::instruction::Instruction;
+use std::collections::BTreeSet;
+use std::fmt;
+
+pub const HELP: &str = "\
+Commands:
+  s, step [N]            Execute N instructions (default 1)
+  c, continue            Run until a breakpoint, watchpoint or fault
+  u, until <ADDR>        Run to ADDR
+  b, break <ADDR>        Break when pc reaches ADDR
+  bo, break-op <PATTERN> Break before an opcode matching PATTERN, x/y/n/k are wildcards e.g. Dxyn
+  w, watch <ADDR> [LEN] [r|w|rw]
+                         Break when memory is read and/or written (default rw)
+  d, delete <N>          Delete breakpoint or watchpoint N, as numbered by list
+  l, list                List breakpoints and watchpoints
+  r, regs                Dump registers
+  k, stack               Dump the stack
+  t, timers              Dump the timers
+  x <ADDR> [LEN]         Hexdump memory (default 64 bytes)
+  h, help                Show this help
+  q, quit                Quit the emulator";
+
+/// Opcode with wildcard nibbles, an opcode matches when `opcode & mask == value`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpcodePattern {
+    mask: u16,
+    value: u16,
+}
+
+impl OpcodePattern {
+    /// Parse four nibbles, each a hex digit or one of the wildcards x, y, n, k, _.
+    pub fn parse(text: &str) -> Option<Self> {
+        if text.chars().count() != 4 {
+            return None;
+        }
+        let mut pattern = OpcodePattern { mask: 0, value: 0 };
+        for c in text.chars() {
+            pattern.mask <<= 4;
+            pattern.value <<= 4;
+            if let Some(digit) = c.to_digit(16) {
+                pattern.mask |= 0xF;
+                pattern.value |= digit as u16;
+            } else if !"xynk_".contains(c.to_ascii_lowercase()) {
+                return None;
+            }
+        }
+        Some(pattern)
+    }
+
+    pub fn matches(&self, opcode: u16) -> bool {
+        opcode & self.mask == self.value
+    }
+}
+
+impl fmt::Display for OpcodePattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for shift in [12, 8, 4, 0] {
+            if (self.mask >> shift) & 0xF == 0 {
+                write!(f, "_")?;
+            } else {
+                write!(f, "{:X}", (self.value >> shift) & 0xF)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub addr: usize,
+    pub len: usize,
+    pub read: bool,
+    pub write: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Breakpoint {
+    Address(u16),
+    Opcode(OpcodePattern),
+    Watch(Watchpoint),
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Breakpoint::Address(addr) => write!(f, "break at 0x{:03X}", addr),
+            Breakpoint::Opcode(pattern) => write!(f, "break on opcode {}", pattern),
+            Breakpoint::Watch(watch) => {
+                let kind = match (watch.read, watch.write) {
+                    (true, true) => "rw",
+                    (true, false) => "r",
+                    _ => "w",
+                };
+                write!(
+                    f,
+                    "watch {} 0x{:03X} - 0x{:03X}",
+                    kind,
+                    watch.addr,
+                    watch.addr + watch.len - 1
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Step(u32),
+    Continue,
+    RunTo(u16),
+    Break(Breakpoint),
+    Delete(usize),
+    List,
+    Registers,
+    Stack,
+    Timers,
+    Dump { addr: usize, len: usize },
+    Help,
+    Quit,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, String> {
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or("step");
+        let args: Vec<&str> = words.collect();
+        let arg = |i: usize| args.get(i).copied();
+
+        let command = match name {
+            "s" | "step" => Command::Step(match arg(0) {
+                Some(count) => count
+                    .parse()
+                    .map_err(|_| format!("bad count '{}'", count))?,
+                None => 1,
+            }),
+            "c" | "continue" => Command::Continue,
+            "u" | "until" => Command::RunTo(parse_address(arg(0))? as u16),
+            "b" | "break" => Command::Break(Breakpoint::Address(parse_address(arg(0))? as u16)),
+            "bo" | "break-op" => {
+                let text = arg(0).ok_or("missing opcode pattern")?;
+                let pattern = OpcodePattern::parse(text)
+                    .ok_or_else(|| format!("bad opcode pattern '{}'", text))?;
+                Command::Break(Breakpoint::Opcode(pattern))
+            }
+            "w" | "watch" => {
+                let addr = parse_address(arg(0))?;
+                let len = match arg(1) {
+                    Some(len) => parse_length(len, addr)?,
+                    None => 1,
+                };
+                let (read, write) = match arg(2).unwrap_or("rw") {
+                    "r" => (true, false),
+                    "w" => (false, true),
+                    "rw" => (true, true),
+                    kind => return Err(format!("bad watch kind '{}', expected r, w or rw", kind)),
+                };
+                Command::Break(Breakpoint::Watch(Watchpoint {
+                    addr,
+                    len,
+                    read,
+                    write,
+                }))
+            }
+            "d" | "delete" => {
+                let text = arg(0).ok_or("missing breakpoint number")?;
+                Command::Delete(
+                    text.parse()
+                        .map_err(|_| format!("bad breakpoint number '{}'", text))?,
+                )
+            }
+            "l" | "list" => Command::List,
+            "r" | "regs" => Command::Registers,
+            "k" | "stack" => Command::Stack,
+            "t" | "timers" => Command::Timers,
+            "x" => {
+                let addr = parse_address(arg(0))?;
+                let len = match arg(1) {
+                    Some(len) => parse_length(len, addr)?,
+                    None => 64,
+                };
+                Command::Dump { addr, len }
+            }
+            "h" | "help" => Command::Help,
+            "q" | "quit" => Command::Quit,
+            _ => return Err(format!("unknown command '{}', try help", name)),
+        };
+        Ok(command)
+    }
+}
+
+// Accepts decimal or 0x prefixed hex
+fn parse_number(text: &str) -> Option<usize> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn parse_address(text: Option<&str>) -> Result<usize, String> {
+    let text = text.ok_or("missing address")?;
+    parse_number(text)
+        .filter(|&addr| addr <= u16::MAX as usize)
+        .ok_or_else(|| format!("bad address '{}'", text))
+}
+
+// A nonzero length, cut short at the end of the 16 bit address space
+fn parse_length(text: &str, addr: usize) -> Result<usize, String> {
+    parse_number(text)
+        .filter(|&len| len > 0)
+        .map(|len| len.min(u16::MAX as usize + 1 - addr))
+        .ok_or_else(|| format!("bad length '{}'", text))
+}
+
+/// Why execution stopped and control went back to the user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    Step,
+    Breakpoint(u16),
+    Opcode {
+        pc: u16,
+        opcode: u16,
+    },
+    Watchpoint {
+        pc: u16,
+        addr: usize,
+        kind: AccessKind,
+    },
+    RunTo(u16),
+    Fault(Chip8Error),
+    Exit,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopReason::Step => write!(f, "stepped"),
+            StopReason::Breakpoint(pc) => write!(f, "breakpoint at 0x{:03X}", pc),
+            StopReason::Opcode { pc, opcode } => write!(f, "opcode {:04X} at 0x{:03X}", opcode, pc),
+            StopReason::Watchpoint { pc, addr, kind } => {
+                let kind = if *kind == AccessKind::Read {
+                    "read"
+                } else {
+                    "write"
+                };
+                write!(
+                    f,
+                    "watchpoint {} of 0x{:03X} by instruction at 0x{:03X}",
+                    kind, addr, pc
+                )
+            }
+            StopReason::RunTo(pc) => write!(f, "reached 0x{:03X}", pc),
+            StopReason::Fault(err) => write!(f, "fault: {}", err),
+            StopReason::Exit => write!(f, "program exited"),
+        }
+    }
+}
+
+/// Executes a single instruction, `Chip8::step` or a wrapper around it.
+pub type StepFn<'a> = dyn FnMut(&mut Chip8) -> Result<StepOutcome, Chip8Error> + 'a;
+
+/// Breakpoints and watchpoints checked around every instruction.
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    addresses: BTreeSet<u16>,
+    run_to: Option<u16>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger::default()
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn add(&mut self, breakpoint: Breakpoint) {
+        if let Breakpoint::Address(addr) = breakpoint {
+            self.addresses.insert(addr);
+        }
+        self.breakpoints.push(breakpoint);
+    }
+
+    /// Remove the breakpoint at `index` in `breakpoints`.
+    pub fn delete(&mut self, index: usize) -> Option<Breakpoint> {
+        if index >= self.breakpoints.len() {
+            return None;
+        }
+        let removed = self.breakpoints.remove(index);
+        self.addresses = self
+            .breakpoints
+            .iter()
+            .filter_map(|breakpoint| match breakpoint {
+                Breakpoint::Address(addr) => Some(*addr),
+                _ => None,
+            })
+            .collect();
+        Some(removed)
+    }
+
+    /// Stop the next time pc reaches `addr`.
+    pub fn run_to(&mut self, addr: u16) {
+        self.run_to = Some(addr);
+    }
+
+    /// Execute one instruction, then check it against the watchpoints and the
+    /// next instruction against the breakpoints.
+    pub fn step(&mut self, chip8: &mut Chip8) -> Result<StepOutcome, StopReason> {
+        self.step_with(chip8, &mut Chip8::step)
+    }
+
+    /// Like `step`, executing the instruction with `step` instead of
+    /// `Chip8::step`, such as `Tracer::step`.
+    pub fn step_with(
+        &mut self,
+        chip8: &mut Chip8,
+        step: &mut StepFn,
+    ) -> Result<StepOutcome, StopReason> {
+        chip8.record_memory_accesses(true);
+        let pc = chip8.pc();
+        let outcome = step(chip8).map_err(StopReason::Fault)?;
+        if outcome == StepOutcome::Exit {
+            return Err(StopReason::Exit);
+        }
+
+        for access in chip8.memory_accesses() {
+            for breakpoint in &self.breakpoints {
+                if let Breakpoint::Watch(watch) = breakpoint {
+                    let wanted = match access.kind {
+                        AccessKind::Read => watch.read,
+                        AccessKind::Write => watch.write,
+                    };
+                    let start = access.addr.max(watch.addr);
+                    if wanted && start < (access.addr + access.len).min(watch.addr + watch.len) {
+                        return Err(StopReason::Watchpoint {
+                            pc,
+                            addr: start,
+                            kind: access.kind,
+                        });
+                    }
+                }
+            }
+        }
+
+        // Only stop on a new instruction, not while spinning on LD Vx, K
+        let next = chip8.pc();
+        if next == pc {
+            return Ok(outcome);
+        }
+        if self.run_to == Some(next) {
+            self.run_to = None;
+            return Err(StopReason::RunTo(next));
+        }
+        if self.addresses.contains(&next) {
+            return Err(StopReason::Breakpoint(next));
+        }
+        let memory = chip8.memory();
+        if let (Some(&high), Some(&low)) =
+            (memory.get(next as usize), memory.get(next as usize + 1))
+        {
+            let opcode = (high as u16) << 8 | low as u16;
+            let hit = self.breakpoints.iter().any(|breakpoint| match breakpoint {
+                Breakpoint::Opcode(pattern) => pattern.matches(opcode),
+                _ => false,
+            });
+            if hit {
+                return Err(StopReason::Opcode { pc: next, opcode });
+            }
+        }
+        Ok(outcome)
+    }
+
+    /// Like `Chip8::run_frame`, stopping early if a breakpoint is hit. Timers
+    /// only tick when the whole frame ran.
+    pub fn run_frame(
+        &mut self,
+        chip8: &mut Chip8,
+        cycles_per_frame: u32,
+    ) -> Result<(), StopReason> {
+        self.run_frame_with(chip8, cycles_per_frame, &mut Chip8::step)
+    }
+
+    /// `run_frame` executing instructions with `step`.
+    pub fn run_frame_with(
+        &mut self,
+        chip8: &mut Chip8,
+        cycles_per_frame: u32,
+        step: &mut StepFn,
+    ) -> Result<(), StopReason> {
+        for _ in 0..cycles_per_frame {
+            if self.step_with(chip8, step)? == StepOutcome::WaitingForVblank {
+                break;
+            }
+        }
+        chip8.tick_timers();
+        Ok(())
+    }
+}
+
+pub fn format_registers(chip8: &Chip8) -> String {
+    let registers: Vec<String> = chip8
+        .registers()
+        .iter()
+        .enumerate()
+        .map(|(i, val)| format!("V{:X}:{:02X}", i, val))
+        .collect();
+    let next = Instruction::decode_at(chip8.memory(), chip8.pc() as usize)
+        .map_or_else(String::new, |instruction| instruction.to_string());
+    format!(
+        "{}\n{}\nPC:{:04X} I:{:04X}  {}",
+        registers[..8].join(" "),
+        registers[8..].join(" "),
+        chip8.pc(),
+        chip8.index(),
+        next
+    )
+}
+
+pub fn format_stack(chip8: &Chip8) -> String {
+    if chip8.stack().is_empty() {
+        return String::from("stack empty");
+    }
+    chip8
+        .stack()
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(depth, addr)| format!("{:>2}: {:04X}", depth, addr))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+pub fn format_timers(chip8: &Chip8) -> String {
+    let (delay, sound) = chip8.timers();
+    format!("DT:{:02X} ST:{:02X}", delay, sound)
+}
+
+/// Classic 16 bytes per line hexdump with an ASCII column.
+pub fn hexdump(chip8: &Chip8, addr: usize, len: usize) -> String {
+    let memory = chip8.memory();
+    let end = addr.saturating_add(len).min(memory.len());
+    let mut lines = Vec::new();
+    let mut line_start = addr;
+    while line_start < end {
+        let line_end = (line_start + 16).min(end);
+        let bytes = &memory[line_start..line_end];
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        let ascii: String = bytes
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii_graphic() {
+                    byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        lines.push(format!(
+            "{:04X}  {:<47}  {}",
+            line_start,
+            hex.join(" "),
+            ascii
+        ));
+        line_start = line_end;
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+
+    fn machine(rom: &[u8]) -> Chip8 {
+        let mut chip8 = Chip8::with_seed(Quirks::superchip(), 0);
+        chip8.load(rom, 0x200).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(Command::parse(""), Ok(Command::Step(1)));
+        assert_eq!(Command::parse("s 10"), Ok(Command::Step(10)));
+        assert_eq!(Command::parse("until 0x2A0"), Ok(Command::RunTo(0x2A0)));
+        assert_eq!(
+            Command::parse("b 512"),
+            Ok(Command::Break(Breakpoint::Address(0x200)))
+        );
+        assert_eq!(
+            Command::parse("bo Dxyn"),
+            Ok(Command::Break(Breakpoint::Opcode(
+                OpcodePattern::parse("D___").unwrap()
+            )))
+        );
+        assert_eq!(
+            Command::parse("w 0x300 4 r"),
+            Ok(Command::Break(Breakpoint::Watch(Watchpoint {
+                addr: 0x300,
+                len: 4,
+                read: true,
+                write: false,
+            })))
+        );
+        assert_eq!(
+            Command::parse("x 0x200"),
+            Ok(Command::Dump {
+                addr: 0x200,
+                len: 64
+            })
+        );
+        assert_eq!(Command::parse("d 2"), Ok(Command::Delete(2)));
+    }
+
+    #[test]
+    fn parse_rejects_bad_arguments() {
+        for line in [
+            "s many",
+            "b",
+            "b 0x10000",
+            "bo D12",
+            "bo Gxyn",
+            "w 0x200 0",
+            "w 0x200 1 x",
+            "d",
+            "frobnicate",
+        ] {
+            assert!(Command::parse(line).is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn parse_clamps_lengths_to_the_address_space() {
+        assert_eq!(
+            Command::parse("x 0 0xFFFFFFFFFFFFFFFF"),
+            Ok(Command::Dump {
+                addr: 0,
+                len: 0x10000
+            })
+        );
+        let Ok(Command::Break(Breakpoint::Watch(watch))) =
+            Command::parse("watch 0x200 0xFFFFFFFFFFFFFFFF")
+        else {
+            panic!("not a watchpoint");
+        };
+        assert_eq!(watch.addr + watch.len, 0x10000);
+        assert_eq!(
+            Breakpoint::Watch(watch).to_string(),
+            "watch rw 0x200 - 0xFFFF"
+        );
+    }
+
+    #[test]
+    fn watchpoints_stop_on_matching_accesses() {
+        // LD I, 0x300; LD [I], V1; LD V1, [I]
+        let rom = [0xA3, 0x00, 0xF1, 0x55, 0xF1, 0x65];
+        let mut chip8 = machine(&rom);
+        let mut debugger = Debugger::new();
+        debugger.add(Breakpoint::Watch(Watchpoint {
+            addr: 0x301,
+            len: 1,
+            read: true,
+            write: false,
+        }));
+        assert_eq!(debugger.step(&mut chip8), Ok(StepOutcome::Executed));
+        // Writes 0x300 and 0x301, but only reads are watched
+        assert_eq!(debugger.step(&mut chip8), Ok(StepOutcome::Executed));
+        assert_eq!(
+            debugger.step(&mut chip8),
+            Err(StopReason::Watchpoint {
+                pc: 0x204,
+                addr: 0x301,
+                kind: AccessKind::Read,
+            })
+        );
+    }
+
+    #[test]
+    fn watchpoints_ignore_accesses_outside_their_range() {
+        // LD I, 0x300; LD [I], V1
+        let rom = [0xA3, 0x00, 0xF1, 0x55];
+        let mut chip8 = machine(&rom);
+        let mut debugger = Debugger::new();
+        debugger.add(Breakpoint::Watch(Watchpoint {
+            addr: 0x302,
+            len: 2,
+            read: true,
+            write: true,
+        }));
+        assert_eq!(debugger.step(&mut chip8), Ok(StepOutcome::Executed));
+        assert_eq!(debugger.step(&mut chip8), Ok(StepOutcome::Executed));
+    }
+
+    #[test]
+    fn run_frame_stops_on_exit() {
+        let mut chip8 = machine(&[0x00, 0xFD]);
+        let mut debugger = Debugger::new();
+        assert_eq!(debugger.run_frame(&mut chip8, 10), Err(StopReason::Exit));
+    }
+}
@@ -0,0 +1,132 @@
+use crate::instruction::Instruction;
+use std::fmt;
+
+/// One line of a disassembly, either a decoded instruction or a byte of data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub addr: usize,
+    pub bytes: Vec<u8>,
+    /// None when the bytes were never reached from the entry point.
+    pub instruction: Option<Instruction>,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let raw: String = self
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        write!(f, "0x{:04X}  {:<10} ", self.addr, raw)?;
+        match self.instruction {
+            Some(instruction) => write!(f, "{}", instruction),
+            // Data is usually sprites, so show the bit pattern too
+            None => {
+                let byte = self.bytes[0];
+                let bits: String = (0..8)
+                    .rev()
+                    .map(|bit| if byte >> bit & 1 == 1 { '#' } else { '.' })
+                    .collect();
+                write!(f, "DB 0x{:02X}     ; {}", byte, bits)
+            }
+        }
+    }
+}
+
+/// Disassemble `bytes` loaded at `origin`, which is also the entry point.
+///
+/// Only instructions reachable from the entry point by following jumps, calls
+/// and skips are decoded, everything else is listed a byte at a time as data.
+/// Targets of `JP V0, addr` depend on V0 so are not followed.
+pub fn disassemble(bytes: &[u8], origin: u16) -> Vec<Line> {
+    let origin = origin as usize;
+    let mut starts = vec![None; bytes.len()];
+    let mut covered = vec![false; bytes.len()];
+    let mut pending = vec![origin];
+
+    while let Some(addr) = pending.pop() {
+        let offset = match addr.checked_sub(origin) {
+            Some(offset) if offset < bytes.len() => offset,
+            _ => continue,
+        };
+        if covered[offset] {
+            continue;
+        }
+        let instruction = match Instruction::decode_at(bytes, offset) {
+            Some(Instruction::Unknown(_)) | None => continue,
+            Some(instruction) => instruction,
+        };
+        let end = offset + instruction.size();
+        if covered[offset..end].iter().any(|&covered| covered) {
+            continue;
+        }
+        covered[offset..end]
+            .iter_mut()
+            .for_each(|covered| *covered = true);
+        starts[offset] = Some(instruction);
+
+        let next = addr + instruction.size();
+        match instruction {
+            Instruction::Jump(target) => pending.push(target as usize),
+            Instruction::Call(target) => pending.extend([target as usize, next]),
+            Instruction::Return | Instruction::Exit | Instruction::JumpOffset(_) => {}
+            _ if instruction.is_skip() => {
+                // Skipping over F000 nnnn skips all four bytes
+                let skipped =
+                    Instruction::decode_at(bytes, next - origin).map_or(2, |next| next.size());
+                pending.extend([next, next + skipped]);
+            }
+            _ => pending.push(next),
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let len = starts[offset].map_or(1, |instruction| instruction.size());
+        lines.push(Line {
+            addr: origin + offset,
+            bytes: bytes[offset..offset + len].to_vec(),
+            instruction: starts[offset],
+        });
+        offset += len;
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_after_a_jump_is_not_decoded() {
+        // JP 0x206; two bytes of sprite data; CLS at the jump target
+        let lines = disassemble(&[0x12, 0x06, 0x00, 0xE0, 0xF0, 0x90, 0x00, 0xE0], 0x200);
+        let text: Vec<String> = lines.iter().map(Line::to_string).collect();
+        assert_eq!(
+            text,
+            [
+                "0x0200  1206       JP 0x206",
+                "0x0202  00         DB 0x00     ; ........",
+                "0x0203  E0         DB 0xE0     ; ###.....",
+                "0x0204  F0         DB 0xF0     ; ####....",
+                "0x0205  90         DB 0x90     ; #..#....",
+                "0x0206  00E0       CLS",
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_reach_both_following_instructions() {
+        // SE V0, 0; JP 0x200; CLS
+        let lines = disassemble(&[0x30, 0x00, 0x12, 0x00, 0x00, 0xE0], 0x200);
+        assert!(lines.iter().all(|line| line.instruction.is_some()));
+        assert_eq!(lines[2].instruction, Some(Instruction::Clear));
+    }
+
+    #[test]
+    fn addresses_past_the_top_of_memory_do_not_wrap() {
+        let lines = disassemble(&[0x00, 0xE0, 0x00, 0xE0], 0xFFFE);
+        assert_eq!(lines[1].addr, 0x10000);
+    }
+}
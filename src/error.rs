@@ -0,0 +1,38 @@
+use std::error::Error;
+use std::fmt;
+
+/// Faults raised while executing a rom. `pc` is the address of the faulting instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip8Error {
+    InvalidOpcode { pc: u16, opcode: u16 },
+    StackOverflow { pc: u16, opcode: u16 },
+    StackUnderflow { pc: u16, opcode: u16 },
+    MemoryOutOfBounds { pc: u16, opcode: u16, addr: usize },
+    PcOutOfBounds { pc: u16 },
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Chip8Error::InvalidOpcode { pc, opcode } => {
+                write!(f, "invalid opcode {:04X} at 0x{:03X}", opcode, pc)
+            }
+            Chip8Error::StackOverflow { pc, opcode } => {
+                write!(f, "stack overflow by {:04X} at 0x{:03X}", opcode, pc)
+            }
+            Chip8Error::StackUnderflow { pc, opcode } => {
+                write!(f, "stack underflow by {:04X} at 0x{:03X}", opcode, pc)
+            }
+            Chip8Error::MemoryOutOfBounds { pc, opcode, addr } => write!(
+                f,
+                "memory access at 0x{:X} out of bounds by {:04X} at 0x{:03X}",
+                addr, opcode, pc
+            ),
+            Chip8Error::PcOutOfBounds { pc } => {
+                write!(f, "program counter 0x{:X} ran past the end of memory", pc)
+            }
+        }
+    }
+}
+
+impl Error for Chip8Error {}
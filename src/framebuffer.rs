@@ -0,0 +1,138 @@
+use crate::hash;
+
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+/// Display of 64x32 in low resolution or 128x64 in SUPER-CHIP high resolution.
+///
+/// Each pixel holds one bit per XO-CHIP bit plane, plain CHIP-8 only ever
+/// draws to plane 1. Operations take a mask of the planes they apply to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    /// Rebuild a framebuffer from `pixels`, which must hold width * height values.
+    pub fn from_pixels(width: usize, height: usize, pixels: Vec<u8>) -> Option<Self> {
+        if pixels.len() != width * height {
+            return None;
+        }
+        Some(Framebuffer {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Every pixel, row by row from the top left.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Rows of pixels from top to bottom.
+    pub fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        self.pixels.chunks(self.width)
+    }
+
+    /// 32 bit FNV-1a hash of `pixels`, for comparing displays cheaply.
+    pub fn hash(&self) -> u32 {
+        hash::fnv1a(&self.pixels)
+    }
+
+    /// XOR a pixel on in the given plane, returning true if it was already set (a collision).
+    pub fn toggle(&mut self, x: usize, y: usize, plane: u8) -> bool {
+        let pixel = &mut self.pixels[y * self.width + x];
+        let collision = *pixel & plane != 0;
+        *pixel ^= plane;
+        collision
+    }
+
+    pub fn clear(&mut self, planes: u8) {
+        for pixel in self.pixels.iter_mut() {
+            *pixel &= !planes;
+        }
+    }
+
+    pub fn scroll_down(&mut self, n: usize, planes: u8) {
+        let n = n.min(self.height);
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                let src = if y >= n {
+                    self.pixels[(y - n) * self.width + x]
+                } else {
+                    0
+                };
+                self.blend(x, y, src, planes);
+            }
+        }
+    }
+
+    pub fn scroll_up(&mut self, n: usize, planes: u8) {
+        let n = n.min(self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let src = if y + n < self.height {
+                    self.pixels[(y + n) * self.width + x]
+                } else {
+                    0
+                };
+                self.blend(x, y, src, planes);
+            }
+        }
+    }
+
+    pub fn scroll_left(&mut self, n: usize, planes: u8) {
+        let n = n.min(self.width);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let src = if x + n < self.width {
+                    self.pixels[y * self.width + x + n]
+                } else {
+                    0
+                };
+                self.blend(x, y, src, planes);
+            }
+        }
+    }
+
+    pub fn scroll_right(&mut self, n: usize, planes: u8) {
+        let n = n.min(self.width);
+        for y in 0..self.height {
+            for x in (0..self.width).rev() {
+                let src = if x >= n {
+                    self.pixels[y * self.width + x - n]
+                } else {
+                    0
+                };
+                self.blend(x, y, src, planes);
+            }
+        }
+    }
+
+    // Replace only the selected planes of a pixel, used when scrolling
+    fn blend(&mut self, x: usize, y: usize, src: u8, planes: u8) {
+        let pixel = &mut self.pixels[y * self.width + x];
+        *pixel = (*pixel & !planes) | (src & planes);
+    }
+}
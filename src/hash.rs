@@ -0,0 +1,6 @@
+/// 32 bit FNV-1a, used to fingerprint ROMs and displays.
+pub fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811C_9DC5, |hash, &byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
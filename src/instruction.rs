@@ -0,0 +1,378 @@
+use std::fmt;
+
+/// A decoded CHIP-8, SUPER-CHIP or XO-CHIP instruction. `x` and `y` are
+/// register numbers. Displays as Cowgod style assembly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    ScrollDown(u8),                // 00Cn SCD nibble
+    ScrollUp(u8),                  // 00Dn SCU nibble
+    Clear,                         // 00E0 CLS
+    Return,                        // 00EE RET
+    ScrollRight,                   // 00FB SCR
+    ScrollLeft,                    // 00FC SCL
+    Exit,                          // 00FD EXIT
+    LowRes,                        // 00FE LOW
+    HighRes,                       // 00FF HIGH
+    Jump(u16),                     // 1nnn JP addr
+    Call(u16),                     // 2nnn CALL addr
+    SkipEqImm { x: u8, byte: u8 }, // 3xkk SE Vx, byte
+    SkipNeImm { x: u8, byte: u8 }, // 4xkk SNE Vx, byte
+    SkipEq { x: u8, y: u8 },       // 5xy0 SE Vx, Vy
+    SaveRange { x: u8, y: u8 },    // 5xy2 SAVE Vx, Vy
+    LoadRange { x: u8, y: u8 },    // 5xy3 LOAD Vx, Vy
+    LoadImm { x: u8, byte: u8 },   // 6xkk LD Vx, byte
+    AddImm { x: u8, byte: u8 },    // 7xkk ADD Vx, byte
+    Move { x: u8, y: u8 },         // 8xy0 LD Vx, Vy
+    Or { x: u8, y: u8 },           // 8xy1 OR Vx, Vy
+    And { x: u8, y: u8 },          // 8xy2 AND Vx, Vy
+    Xor { x: u8, y: u8 },          // 8xy3 XOR Vx, Vy
+    Add { x: u8, y: u8 },          // 8xy4 ADD Vx, Vy
+    Sub { x: u8, y: u8 },          // 8xy5 SUB Vx, Vy
+    ShiftRight { x: u8, y: u8 },   // 8xy6 SHR Vx, Vy
+    SubN { x: u8, y: u8 },         // 8xy7 SUBN Vx, Vy
+    ShiftLeft { x: u8, y: u8 },    // 8xyE SHL Vx, Vy
+    SkipNe { x: u8, y: u8 },       // 9xy0 SNE Vx, Vy
+    LoadIndex(u16),                // Annn LD I, addr
+    JumpOffset(u16),               // Bnnn JP V0, addr
+    Random { x: u8, byte: u8 },    // Cxkk RND Vx, byte
+    Draw { x: u8, y: u8, n: u8 },  // Dxyn DRW Vx, Vy, nibble
+    SkipKey { x: u8 },             // Ex9E SKP Vx
+    SkipNotKey { x: u8 },          // ExA1 SKNP Vx
+    LongIndex(u16),                // F000 nnnn LD I, LONG addr
+    Plane(u8),                     // Fn01 PLANE n
+    Audio,                         // F002 AUDIO
+    LoadDelay { x: u8 },           // Fx07 LD Vx, DT
+    WaitKey { x: u8 },             // Fx0A LD Vx, K
+    SetDelay { x: u8 },            // Fx15 LD DT, Vx
+    SetSound { x: u8 },            // Fx18 LD ST, Vx
+    AddIndex { x: u8 },            // Fx1E ADD I, Vx
+    Font { x: u8 },                // Fx29 LD F, Vx
+    BigFont { x: u8 },             // Fx30 LD HF, Vx
+    Bcd { x: u8 },                 // Fx33 LD B, Vx
+    Pitch { x: u8 },               // Fx3A PITCH Vx
+    Store { x: u8 },               // Fx55 LD [I], Vx
+    Load { x: u8 },                // Fx65 LD Vx, [I]
+    StoreFlags { x: u8 },          // Fx75 LD R, Vx
+    LoadFlags { x: u8 },           // Fx85 LD Vx, R
+    Unknown(u16),
+}
+
+impl Instruction {
+    /// Decode `opcode`. `next` is the word following it, only needed by the
+    /// four byte XO-CHIP F000 nnnn, which is unknown without it.
+    pub fn decode(opcode: u16, next: Option<u16>) -> Instruction {
+        let addr = opcode & 0x0FFF;
+        let x = ((opcode & 0x0F00) >> 8) as u8;
+        let y = ((opcode & 0x00F0) >> 4) as u8;
+        let byte = (opcode & 0x00FF) as u8;
+        let n = (opcode & 0x000F) as u8;
+
+        match opcode & 0xF000 {
+            0x0000 => match opcode {
+                0x00C0..=0x00CF => Instruction::ScrollDown(n),
+                0x00D0..=0x00DF => Instruction::ScrollUp(n),
+                0x00E0 => Instruction::Clear,
+                0x00EE => Instruction::Return,
+                0x00FB => Instruction::ScrollRight,
+                0x00FC => Instruction::ScrollLeft,
+                0x00FD => Instruction::Exit,
+                0x00FE => Instruction::LowRes,
+                0x00FF => Instruction::HighRes,
+                _ => Instruction::Unknown(opcode),
+            },
+            0x1000 => Instruction::Jump(addr),
+            0x2000 => Instruction::Call(addr),
+            0x3000 => Instruction::SkipEqImm { x, byte },
+            0x4000 => Instruction::SkipNeImm { x, byte },
+            0x5000 => match n {
+                0x0 => Instruction::SkipEq { x, y },
+                0x2 => Instruction::SaveRange { x, y },
+                0x3 => Instruction::LoadRange { x, y },
+                _ => Instruction::Unknown(opcode),
+            },
+            0x6000 => Instruction::LoadImm { x, byte },
+            0x7000 => Instruction::AddImm { x, byte },
+            0x8000 => match n {
+                0x0 => Instruction::Move { x, y },
+                0x1 => Instruction::Or { x, y },
+                0x2 => Instruction::And { x, y },
+                0x3 => Instruction::Xor { x, y },
+                0x4 => Instruction::Add { x, y },
+                0x5 => Instruction::Sub { x, y },
+                0x6 => Instruction::ShiftRight { x, y },
+                0x7 => Instruction::SubN { x, y },
+                0xE => Instruction::ShiftLeft { x, y },
+                _ => Instruction::Unknown(opcode),
+            },
+            0x9000 if n == 0 => Instruction::SkipNe { x, y },
+            0xA000 => Instruction::LoadIndex(addr),
+            0xB000 => Instruction::JumpOffset(addr),
+            0xC000 => Instruction::Random { x, byte },
+            0xD000 => Instruction::Draw { x, y, n },
+            0xE000 => match byte {
+                0x9E => Instruction::SkipKey { x },
+                0xA1 => Instruction::SkipNotKey { x },
+                _ => Instruction::Unknown(opcode),
+            },
+            0xF000 => match byte {
+                0x00 if x == 0 => match next {
+                    Some(long_addr) => Instruction::LongIndex(long_addr),
+                    None => Instruction::Unknown(opcode),
+                },
+                0x01 => Instruction::Plane(x),
+                0x02 if x == 0 => Instruction::Audio,
+                0x07 => Instruction::LoadDelay { x },
+                0x0A => Instruction::WaitKey { x },
+                0x15 => Instruction::SetDelay { x },
+                0x18 => Instruction::SetSound { x },
+                0x1E => Instruction::AddIndex { x },
+                0x29 => Instruction::Font { x },
+                0x30 => Instruction::BigFont { x },
+                0x33 => Instruction::Bcd { x },
+                0x3A => Instruction::Pitch { x },
+                0x55 => Instruction::Store { x },
+                0x65 => Instruction::Load { x },
+                0x75 => Instruction::StoreFlags { x },
+                0x85 => Instruction::LoadFlags { x },
+                _ => Instruction::Unknown(opcode),
+            },
+            _ => Instruction::Unknown(opcode),
+        }
+    }
+
+    /// Decode the instruction at `addr`, None if it runs off the end of `memory`.
+    pub fn decode_at(memory: &[u8], addr: usize) -> Option<Instruction> {
+        let word =
+            |addr: usize| Some((*memory.get(addr)? as u16) << 8 | *memory.get(addr + 1)? as u16);
+        let opcode = word(addr)?;
+        Some(Instruction::decode(opcode, word(addr + 2)))
+    }
+
+    /// Encode back into bytes, the inverse of `decode`.
+    pub fn encode(&self) -> Vec<u8> {
+        let xy = |high: u16, x: u8, y: u8, low: u16| high | (x as u16) << 8 | (y as u16) << 4 | low;
+        let xkk = |high: u16, x: u8, byte: u8| high | (x as u16) << 8 | byte as u16;
+        let opcode = match *self {
+            Instruction::ScrollDown(n) => 0x00C0 | n as u16,
+            Instruction::ScrollUp(n) => 0x00D0 | n as u16,
+            Instruction::Clear => 0x00E0,
+            Instruction::Return => 0x00EE,
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::LowRes => 0x00FE,
+            Instruction::HighRes => 0x00FF,
+            Instruction::Jump(addr) => 0x1000 | addr,
+            Instruction::Call(addr) => 0x2000 | addr,
+            Instruction::SkipEqImm { x, byte } => xkk(0x3000, x, byte),
+            Instruction::SkipNeImm { x, byte } => xkk(0x4000, x, byte),
+            Instruction::SkipEq { x, y } => xy(0x5000, x, y, 0x0),
+            Instruction::SaveRange { x, y } => xy(0x5000, x, y, 0x2),
+            Instruction::LoadRange { x, y } => xy(0x5000, x, y, 0x3),
+            Instruction::LoadImm { x, byte } => xkk(0x6000, x, byte),
+            Instruction::AddImm { x, byte } => xkk(0x7000, x, byte),
+            Instruction::Move { x, y } => xy(0x8000, x, y, 0x0),
+            Instruction::Or { x, y } => xy(0x8000, x, y, 0x1),
+            Instruction::And { x, y } => xy(0x8000, x, y, 0x2),
+            Instruction::Xor { x, y } => xy(0x8000, x, y, 0x3),
+            Instruction::Add { x, y } => xy(0x8000, x, y, 0x4),
+            Instruction::Sub { x, y } => xy(0x8000, x, y, 0x5),
+            Instruction::ShiftRight { x, y } => xy(0x8000, x, y, 0x6),
+            Instruction::SubN { x, y } => xy(0x8000, x, y, 0x7),
+            Instruction::ShiftLeft { x, y } => xy(0x8000, x, y, 0xE),
+            Instruction::SkipNe { x, y } => xy(0x9000, x, y, 0x0),
+            Instruction::LoadIndex(addr) => 0xA000 | addr,
+            Instruction::JumpOffset(addr) => 0xB000 | addr,
+            Instruction::Random { x, byte } => xkk(0xC000, x, byte),
+            Instruction::Draw { x, y, n } => xy(0xD000, x, y, n as u16),
+            Instruction::SkipKey { x } => xkk(0xE000, x, 0x9E),
+            Instruction::SkipNotKey { x } => xkk(0xE000, x, 0xA1),
+            Instruction::LongIndex(addr) => return vec![0xF0, 0x00, (addr >> 8) as u8, addr as u8],
+            Instruction::Plane(n) => xkk(0xF000, n, 0x01),
+            Instruction::Audio => 0xF002,
+            Instruction::LoadDelay { x } => xkk(0xF000, x, 0x07),
+            Instruction::WaitKey { x } => xkk(0xF000, x, 0x0A),
+            Instruction::SetDelay { x } => xkk(0xF000, x, 0x15),
+            Instruction::SetSound { x } => xkk(0xF000, x, 0x18),
+            Instruction::AddIndex { x } => xkk(0xF000, x, 0x1E),
+            Instruction::Font { x } => xkk(0xF000, x, 0x29),
+            Instruction::BigFont { x } => xkk(0xF000, x, 0x30),
+            Instruction::Bcd { x } => xkk(0xF000, x, 0x33),
+            Instruction::Pitch { x } => xkk(0xF000, x, 0x3A),
+            Instruction::Store { x } => xkk(0xF000, x, 0x55),
+            Instruction::Load { x } => xkk(0xF000, x, 0x65),
+            Instruction::StoreFlags { x } => xkk(0xF000, x, 0x75),
+            Instruction::LoadFlags { x } => xkk(0xF000, x, 0x85),
+            Instruction::Unknown(opcode) => opcode,
+        };
+        opcode.to_be_bytes().to_vec()
+    }
+
+    /// Size in bytes, 4 for F000 nnnn and 2 for everything else.
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::LongIndex(_) => 4,
+            _ => 2,
+        }
+    }
+
+    /// True for instructions that skip the next instruction on some condition.
+    pub fn is_skip(&self) -> bool {
+        matches!(
+            self,
+            Instruction::SkipEqImm { .. }
+                | Instruction::SkipNeImm { .. }
+                | Instruction::SkipEq { .. }
+                | Instruction::SkipNe { .. }
+                | Instruction::SkipKey { .. }
+                | Instruction::SkipNotKey { .. }
+        )
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::ScrollDown(n) => write!(f, "SCD {}", n),
+            Instruction::ScrollUp(n) => write!(f, "SCU {}", n),
+            Instruction::Clear => write!(f, "CLS"),
+            Instruction::Return => write!(f, "RET"),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::LowRes => write!(f, "LOW"),
+            Instruction::HighRes => write!(f, "HIGH"),
+            Instruction::Jump(addr) => write!(f, "JP 0x{:03X}", addr),
+            Instruction::Call(addr) => write!(f, "CALL 0x{:03X}", addr),
+            Instruction::SkipEqImm { x, byte } => write!(f, "SE V{:X}, 0x{:02X}", x, byte),
+            Instruction::SkipNeImm { x, byte } => write!(f, "SNE V{:X}, 0x{:02X}", x, byte),
+            Instruction::SkipEq { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::SaveRange { x, y } => write!(f, "SAVE V{:X}, V{:X}", x, y),
+            Instruction::LoadRange { x, y } => write!(f, "LOAD V{:X}, V{:X}", x, y),
+            Instruction::LoadImm { x, byte } => write!(f, "LD V{:X}, 0x{:02X}", x, byte),
+            Instruction::AddImm { x, byte } => write!(f, "ADD V{:X}, 0x{:02X}", x, byte),
+            Instruction::Move { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::Add { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::ShiftRight { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SubN { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::ShiftLeft { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SkipNe { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LoadIndex(addr) => write!(f, "LD I, 0x{:03X}", addr),
+            Instruction::JumpOffset(addr) => write!(f, "JP V0, 0x{:03X}", addr),
+            Instruction::Random { x, byte } => write!(f, "RND V{:X}, 0x{:02X}", x, byte),
+            Instruction::Draw { x, y, n } => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::SkipKey { x } => write!(f, "SKP V{:X}", x),
+            Instruction::SkipNotKey { x } => write!(f, "SKNP V{:X}", x),
+            Instruction::LongIndex(addr) => write!(f, "LD I, LONG 0x{:04X}", addr),
+            Instruction::Plane(n) => write!(f, "PLANE {}", n),
+            Instruction::Audio => write!(f, "AUDIO"),
+            Instruction::LoadDelay { x } => write!(f, "LD V{:X}, DT", x),
+            Instruction::WaitKey { x } => write!(f, "LD V{:X}, K", x),
+            Instruction::SetDelay { x } => write!(f, "LD DT, V{:X}", x),
+            Instruction::SetSound { x } => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddIndex { x } => write!(f, "ADD I, V{:X}", x),
+            Instruction::Font { x } => write!(f, "LD F, V{:X}", x),
+            Instruction::BigFont { x } => write!(f, "LD HF, V{:X}", x),
+            Instruction::Bcd { x } => write!(f, "LD B, V{:X}", x),
+            Instruction::Pitch { x } => write!(f, "PITCH V{:X}", x),
+            Instruction::Store { x } => write!(f, "LD [I], V{:X}", x),
+            Instruction::Load { x } => write!(f, "LD V{:X}, [I]", x),
+            Instruction::StoreFlags { x } => write!(f, "LD R, V{:X}", x),
+            Instruction::LoadFlags { x } => write!(f, "LD V{:X}, R", x),
+            Instruction::Unknown(opcode) => write!(f, "DW 0x{:04X}", opcode),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(opcode: u16) -> String {
+        Instruction::decode(opcode, None).to_string()
+    }
+
+    #[test]
+    fn display_system_and_flow() {
+        assert_eq!(text(0x00C3), "SCD 3");
+        assert_eq!(text(0x00D4), "SCU 4");
+        assert_eq!(text(0x00E0), "CLS");
+        assert_eq!(text(0x00EE), "RET");
+        assert_eq!(text(0x00FB), "SCR");
+        assert_eq!(text(0x00FC), "SCL");
+        assert_eq!(text(0x00FD), "EXIT");
+        assert_eq!(text(0x00FE), "LOW");
+        assert_eq!(text(0x00FF), "HIGH");
+        assert_eq!(text(0x1234), "JP 0x234");
+        assert_eq!(text(0x2ABC), "CALL 0xABC");
+        assert_eq!(text(0xB300), "JP V0, 0x300");
+    }
+
+    #[test]
+    fn display_skips() {
+        assert_eq!(text(0x3A12), "SE VA, 0x12");
+        assert_eq!(text(0x4B34), "SNE VB, 0x34");
+        assert_eq!(text(0x5120), "SE V1, V2");
+        assert_eq!(text(0x9340), "SNE V3, V4");
+        assert_eq!(text(0xE59E), "SKP V5");
+        assert_eq!(text(0xE6A1), "SKNP V6");
+    }
+
+    #[test]
+    fn display_registers_and_arithmetic() {
+        assert_eq!(text(0x5122), "SAVE V1, V2");
+        assert_eq!(text(0x5123), "LOAD V1, V2");
+        assert_eq!(text(0x60FF), "LD V0, 0xFF");
+        assert_eq!(text(0x7F01), "ADD VF, 0x01");
+        assert_eq!(text(0x8120), "LD V1, V2");
+        assert_eq!(text(0x8121), "OR V1, V2");
+        assert_eq!(text(0x8122), "AND V1, V2");
+        assert_eq!(text(0x8123), "XOR V1, V2");
+        assert_eq!(text(0x8124), "ADD V1, V2");
+        assert_eq!(text(0x8125), "SUB V1, V2");
+        assert_eq!(text(0x8126), "SHR V1, V2");
+        assert_eq!(text(0x8127), "SUBN V1, V2");
+        assert_eq!(text(0x812E), "SHL V1, V2");
+        assert_eq!(text(0xC7AA), "RND V7, 0xAA");
+    }
+
+    #[test]
+    fn display_memory_and_drawing() {
+        assert_eq!(text(0xA123), "LD I, 0x123");
+        assert_eq!(
+            Instruction::decode(0xF000, Some(0xBEEF)).to_string(),
+            "LD I, LONG 0xBEEF"
+        );
+        assert_eq!(text(0xD125), "DRW V1, V2, 5");
+        assert_eq!(text(0xF201), "PLANE 2");
+        assert_eq!(text(0xF002), "AUDIO");
+        assert_eq!(text(0xF11E), "ADD I, V1");
+        assert_eq!(text(0xF229), "LD F, V2");
+        assert_eq!(text(0xF330), "LD HF, V3");
+        assert_eq!(text(0xF433), "LD B, V4");
+        assert_eq!(text(0xF555), "LD [I], V5");
+        assert_eq!(text(0xF665), "LD V6, [I]");
+        assert_eq!(text(0xF775), "LD R, V7");
+        assert_eq!(text(0xF885), "LD V8, R");
+    }
+
+    #[test]
+    fn display_timers_keys_and_sound() {
+        assert_eq!(text(0xF107), "LD V1, DT");
+        assert_eq!(text(0xF20A), "LD V2, K");
+        assert_eq!(text(0xF315), "LD DT, V3");
+        assert_eq!(text(0xF418), "LD ST, V4");
+        assert_eq!(text(0xF53A), "PITCH V5");
+    }
+
+    #[test]
+    fn display_unknown_opcodes_as_data() {
+        assert_eq!(text(0x5121), "DW 0x5121");
+        assert_eq!(text(0xF000), "DW 0xF000");
+        assert_eq!(text(0xE1FF), "DW 0xE1FF");
+    }
+}
@@ -0,0 +1,210 @@
+use chip8emu::Chip8;
+use std::time::{Duration, Instant};
+
+// Terminals only report key presses, so a key counts as held until no
+// press or auto-repeat has arrived for this long. Needs to outlast the
+// usual auto-repeat delay.
+pub const RELEASE_TIMEOUT: Duration = Duration::from_millis(550);
+
+const ESC: u8 = 0x1B;
+const BACKSPACE: u8 = 0x7F;
+
+/// Map the left hand side of a QWERTY keyboard onto the hex keypad.
+///
+/// ```text
+/// 1 2 3 4      1 2 3 C
+/// Q W E R  ->  4 5 6 D
+/// A S D F      7 8 9 E
+/// Z X C V      A 0 B F
+/// ```
+pub fn map_key(byte: u8) -> Option<u8> {
+    let key = match byte.to_ascii_lowercase() {
+        b'1' => 0x1,
+        b'2' => 0x2,
+        b'3' => 0x3,
+        b'4' => 0xC,
+        b'q' => 0x4,
+        b'w' => 0x5,
+        b'e' => 0x6,
+        b'r' => 0xD,
+        b'a' => 0x7,
+        b's' => 0x8,
+        b'd' => 0x9,
+        b'f' => 0xE,
+        b'z' => 0xA,
+        b'x' => 0x0,
+        b'c' => 0xB,
+        b'v' => 0xF,
+        _ => return None,
+    };
+    Some(key)
+}
+
+/// Emulator controls outside the hex keypad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hotkey {
+    SelectSlot(u8),
+    SaveState,
+    LoadState,
+    Break,
+}
+
+/// 5 - 9 pick a save slot, O saves to it and P loads from it. Backtick breaks
+/// into the debugger. Holding backspace rewinds, which is handled like a
+/// keypad key rather than a hotkey.
+pub fn map_hotkey(byte: u8) -> Option<Hotkey> {
+    match byte.to_ascii_lowercase() {
+        b'5'..=b'9' => Some(Hotkey::SelectSlot(byte - b'0')),
+        b'o' => Some(Hotkey::SaveState),
+        b'p' => Some(Hotkey::LoadState),
+        b'`' => Some(Hotkey::Break),
+        _ => None,
+    }
+}
+
+pub struct Keypad {
+    pressed_at: [Option<Instant>; 16],
+    release_timeout: Duration,
+    rewind_pressed_at: Option<Instant>,
+    quit: bool,
+    hotkeys: Vec<Hotkey>,
+}
+
+impl Keypad {
+    pub fn new(release_timeout: Duration) -> Self {
+        Keypad {
+            pressed_at: [None; 16],
+            release_timeout,
+            rewind_pressed_at: None,
+            quit: false,
+            hotkeys: Vec::new(),
+        }
+    }
+
+    /// Record the bytes read from the terminal this frame.
+    pub fn feed(&mut self, bytes: &[u8], now: Instant) {
+        let mut i = 0;
+        while i < bytes.len() {
+            let byte = bytes[i];
+            i += 1;
+            if byte == ESC {
+                // A lone escape quits, escape sequences (arrows etc.) are skipped
+                if matches!(bytes.get(i), Some(b'[') | Some(b'O')) {
+                    i += 1;
+                    while i < bytes.len() && !(0x40..=0x7E).contains(&bytes[i]) {
+                        i += 1;
+                    }
+                    i += 1;
+                } else {
+                    self.quit = true;
+                }
+            } else if byte == BACKSPACE || byte == 0x08 {
+                self.rewind_pressed_at = Some(now);
+            } else if let Some(key) = map_key(byte) {
+                self.pressed_at[key as usize] = Some(now);
+            } else if let Some(hotkey) = map_hotkey(byte) {
+                self.hotkeys.push(hotkey);
+            }
+        }
+    }
+
+    pub fn quit_requested(&self) -> bool {
+        self.quit
+    }
+
+    /// True while the rewind key is held down.
+    pub fn rewind_held(&self, now: Instant) -> bool {
+        self.rewind_pressed_at
+            .is_some_and(|at| now.duration_since(at) <= self.release_timeout)
+    }
+
+    /// Hotkeys pressed since the last call, oldest first.
+    pub fn take_hotkeys(&mut self) -> Vec<Hotkey> {
+        std::mem::take(&mut self.hotkeys)
+    }
+
+    /// Push the current key state into the emulator, releasing keys that have timed out.
+    pub fn update(&mut self, chip8: &mut Chip8, now: Instant) {
+        for (key, pressed_at) in self.pressed_at.iter_mut().enumerate() {
+            if let Some(at) = *pressed_at {
+                if now.duration_since(at) > self.release_timeout {
+                    *pressed_at = None;
+                }
+            }
+            chip8.set_key(key as u8, pressed_at.is_some());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8emu::Quirks;
+
+    #[test]
+    fn qwerty_maps_onto_the_hex_keypad() {
+        assert_eq!(map_key(b'1'), Some(0x1));
+        assert_eq!(map_key(b'4'), Some(0xC));
+        assert_eq!(map_key(b'X'), Some(0x0));
+        assert_eq!(map_key(b'v'), Some(0xF));
+        assert_eq!(map_key(b'5'), None);
+        assert_eq!(map_hotkey(b'5'), Some(Hotkey::SelectSlot(5)));
+        assert_eq!(map_hotkey(b'`'), Some(Hotkey::Break));
+    }
+
+    #[test]
+    fn keys_are_held_until_the_timeout() {
+        let mut keypad = Keypad::new(RELEASE_TIMEOUT);
+        let mut chip8 = Chip8::with_seed(Quirks::chip8(), 0);
+        let now = Instant::now();
+        keypad.feed(b"w", now);
+        keypad.update(&mut chip8, now + RELEASE_TIMEOUT);
+        assert!(chip8.keys()[0x5]);
+        keypad.update(&mut chip8, now + RELEASE_TIMEOUT * 2);
+        assert!(!chip8.keys()[0x5]);
+    }
+
+    #[test]
+    fn escape_sequences_are_skipped() {
+        let mut keypad = Keypad::new(RELEASE_TIMEOUT);
+        let mut chip8 = Chip8::with_seed(Quirks::chip8(), 0);
+        let now = Instant::now();
+        // Up arrow, F1 and ctrl+right, then Q
+        keypad.feed(b"\x1b[A\x1bOP\x1b[1;5Cq", now);
+        keypad.update(&mut chip8, now);
+        assert!(!keypad.quit_requested());
+        // The final bytes A, P and C are not read as keys
+        let held: Vec<usize> = (0..16).filter(|&key| chip8.keys()[key]).collect();
+        assert_eq!(held, [0x4]);
+    }
+
+    #[test]
+    fn a_lone_escape_quits() {
+        let mut keypad = Keypad::new(RELEASE_TIMEOUT);
+        keypad.feed(b"\x1b", Instant::now());
+        assert!(keypad.quit_requested());
+    }
+
+    #[test]
+    fn backspace_rewinds() {
+        let mut keypad = Keypad::new(RELEASE_TIMEOUT);
+        let now = Instant::now();
+        assert!(!keypad.rewind_held(now));
+        keypad.feed(&[BACKSPACE], now);
+        assert!(keypad.rewind_held(now));
+        assert!(!keypad.rewind_held(now + RELEASE_TIMEOUT * 2));
+        keypad.feed(&[0x08], now);
+        assert!(keypad.rewind_held(now));
+    }
+
+    #[test]
+    fn hotkeys_are_taken_in_order() {
+        let mut keypad = Keypad::new(RELEASE_TIMEOUT);
+        keypad.feed(b"7op", Instant::now());
+        assert_eq!(
+            keypad.take_hotkeys(),
+            [Hotkey::SelectSlot(7), Hotkey::SaveState, Hotkey::LoadState]
+        );
+        assert!(keypad.take_hotkeys().is_empty());
+    }
+}
@@ -0,0 +1,137 @@
+use crate::chip::Chip8;
+
+/// A key pressed or released at the start of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub frame: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+/// Scripted keypad input for running without a keyboard. One event per line,
+/// the frame counted from 0, the key in hex and `down` or `up`:
+///
+/// ```text
+/// # start the game then hold 5 for a second
+/// 10 F down
+/// 12 F up
+/// 60 5 down
+/// 120 5 up
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyScript {
+    events: Vec<KeyEvent>,
+}
+
+impl KeyScript {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut events = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = || {
+                format!(
+                    "line {}: expected '<frame> <key> down|up', got '{}'",
+                    i + 1,
+                    line
+                )
+            };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [frame, key, action] = fields[..] else {
+                return Err(error());
+            };
+            let frame = frame.parse().map_err(|_| error())?;
+            let key = u8::from_str_radix(key, 16)
+                .ok()
+                .filter(|&key| key <= 0xF)
+                .ok_or_else(error)?;
+            let pressed = match action {
+                "down" => true,
+                "up" => false,
+                _ => return Err(error()),
+            };
+            events.push(KeyEvent {
+                frame,
+                key,
+                pressed,
+            });
+        }
+        // Stable so events on the same frame keep their order
+        events.sort_by_key(|event| event.frame);
+        Ok(KeyScript { events })
+    }
+
+    pub fn events(&self) -> &[KeyEvent] {
+        &self.events
+    }
+
+    /// Apply the events for `frame`, call before running it.
+    pub fn apply(&self, frame: u64, chip8: &mut Chip8) {
+        for event in self.events.iter().filter(|event| event.frame == frame) {
+            chip8.set_key(event.key, event.pressed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+
+    fn event(frame: u64, key: u8, pressed: bool) -> KeyEvent {
+        KeyEvent {
+            frame,
+            key,
+            pressed,
+        }
+    }
+
+    #[test]
+    fn events_are_sorted_by_frame() {
+        let script = KeyScript::parse("# comment\n\n20 a up  # trailing\n10 A down\n").unwrap();
+        assert_eq!(
+            script.events(),
+            [event(10, 0xA, true), event(20, 0xA, false)]
+        );
+    }
+
+    #[test]
+    fn events_on_the_same_frame_keep_their_order() {
+        let script = KeyScript::parse("5 1 down\n3 2 down\n5 1 up\n5 3 down").unwrap();
+        assert_eq!(
+            script.events(),
+            [
+                event(3, 0x2, true),
+                event(5, 0x1, true),
+                event(5, 0x1, false),
+                event(5, 0x3, true),
+            ]
+        );
+        // Pressed then released within the frame leaves the key up
+        let mut chip8 = Chip8::with_seed(Quirks::chip8(), 0);
+        script.apply(5, &mut chip8);
+        assert!(!chip8.keys()[0x1]);
+        assert!(chip8.keys()[0x3]);
+        assert!(!chip8.keys()[0x2]);
+    }
+
+    #[test]
+    fn malformed_lines_report_their_number() {
+        let cases = [
+            ("1 5 down\nsoon 5 down", "line 2: "),
+            ("-1 5 down", "line 1: "),
+            ("\n\n1 G down", "line 3: "),
+            ("1 10 down", "line 1: "),
+            ("1 5 pressed", "line 1: "),
+            ("# header\n1 5", "line 2: "),
+            ("1 5 down up", "line 1: "),
+        ];
+        for (text, prefix) in cases {
+            let err = KeyScript::parse(text).unwrap_err();
+            assert!(err.starts_with(prefix), "{:?}: {}", text, err);
+            assert!(err.contains("expected '<frame> <key> down|up'"), "{}", err);
+        }
+    }
+}
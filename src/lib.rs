@@ -0,0 +1,30 @@
+//! CHIP-8, SUPER-CHIP and XO-CHIP interpreter core. `Chip8` itself does no
+//! I/O, frontends drive it and read its state back to present it. The tools
+//! around it mostly hand back bytes or text for the caller to write out, the
+//! WAV writer writes to a `Write` it is given.
+
+pub mod asm;
+pub mod audio;
+pub mod chip;
+pub mod compare;
+pub mod debugger;
+pub mod disasm;
+pub mod error;
+pub mod framebuffer;
+pub mod hash;
+pub mod instruction;
+pub mod keyscript;
+pub mod movie;
+pub mod quirks;
+pub mod rewind;
+pub mod rng;
+pub mod screenshot;
+pub mod state;
+pub mod trace;
+
+pub use chip::{Chip8, StepOutcome};
+pub use error::Chip8Error;
+pub use framebuffer::Framebuffer;
+pub use instruction::Instruction;
+pub use quirks::Quirks;
+pub use state::StateError;
@@ -1,49 +1,505 @@
-
-use std::io::{self, Read};
-use std::os::unix::io::AsRawFd;
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::process;
 use std::thread;
 use std::time::{Duration, Instant};
-use termios::*;
 
-mod chip;
+mod cli;
+mod console;
+mod keypad;
+mod render;
+mod speaker;
+mod terminal;
 
-use chip::Chip8;
+use chip8emu::audio::{AudioBackend, Tone, WavWriter};
+use chip8emu::compare::{self, Comparison};
+use chip8emu::debugger::{Debugger, StopReason};
+use chip8emu::keyscript::KeyScript;
+use chip8emu::movie::Movie;
+use chip8emu::rewind::Rewind;
+use chip8emu::rng;
+use chip8emu::trace::Tracer;
+use chip8emu::{asm, disasm};
+use chip8emu::{Chip8, Chip8Error, StepOutcome};
+use cli::{AudioOutput, CliError, Command, CompareOptions, Options, RendererKind};
+use console::Resume;
+use keypad::{Hotkey, Keypad};
+use render::{NullRenderer, Renderer, TerminalRenderer};
+use speaker::Aplay;
+use terminal::RawTerminal;
 
 fn main() {
-    // Setup std in for keyboard input
-    let stdin = io::stdin().as_raw_fd();
-    let original_term = Termios::from_fd(stdin).unwrap();
-    let mut termios = original_term.clone();
-    // Change input to read buffer rather than line and remove echo
-    termios.c_lflag &= !(ECHO | ICANON);
-    tcsetattr(stdin, TCSANOW, &mut termios).unwrap();
-    let mut reader = io::stdin();
-    let mut buffer = [0; 1]; // read exactly one byte
- 
-
-    let mut chip8 = Chip8::new();
-    
-    // Temp manual rom to print sprite and wait for input
-    let test_binary: [u8; 8] = [0x63, 0x01, 0xF3, 0x29, 0xD0, 0x05, 0xF0, 0x0A];
-    chip8.load(test_binary.to_vec()).unwrap();
-
- 
-    let emu_speed = Duration::from_secs_f64(1.0 / 60.0); // Default 60hz
+    let options = match cli::parse(env::args().skip(1)) {
+        Ok(Command::Run(options)) => options,
+        Ok(Command::Disasm { rom, start }) => {
+            let rom_bytes = read_rom(&rom);
+            for line in disasm::disassemble(&rom_bytes, start) {
+                println!("{}", line);
+            }
+            return;
+        }
+        Ok(Command::Asm {
+            source,
+            output,
+            start,
+        }) => {
+            let rom = match asm::assemble_file(&source, start, |path| fs::read_to_string(path)) {
+                Ok(rom) => rom,
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    process::exit(1);
+                }
+            };
+            if let Err(err) = fs::write(&output, rom) {
+                eprintln!("error: could not write '{}': {}", output, err);
+                process::exit(1);
+            }
+            return;
+        }
+        Ok(Command::Compare(options)) => process::exit(compare(&options)),
+        Err(CliError::Help) => {
+            println!("{}", cli::USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, cli::USAGE);
+            process::exit(2);
+        }
+    };
+
+    let mut options = options;
+    let rom = read_rom(&options.rom);
+    let mut seed = options.seed.unwrap_or_else(rng::random_seed);
+    let mut movie = None;
+    if let Some(path) = &options.play {
+        let playback = match fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|text| Movie::parse(&text))
+        {
+            Ok(playback) => playback,
+            Err(err) => {
+                eprintln!("error: could not read movie '{}': {}", path, err);
+                process::exit(1);
+            }
+        };
+        if !playback.matches_rom(&rom) {
+            eprintln!("error: movie '{}' was recorded with a different ROM", path);
+            process::exit(1);
+        }
+        // Everything that affects execution has to match the recording
+        options.quirks = playback.quirks;
+        options.cycles_per_frame = playback.cycles_per_frame;
+        options.start = playback.start;
+        seed = playback.seed;
+        movie = Some(MovieMode::Play(playback));
+    } else if options.record.is_some() {
+        match Movie::new(
+            &rom,
+            options.quirks,
+            seed,
+            options.cycles_per_frame,
+            options.start,
+        ) {
+            Ok(recording) => movie = Some(MovieMode::Record(recording)),
+            Err(err) => {
+                eprintln!("error: could not record a movie: {}", err);
+                process::exit(1);
+            }
+        }
+    }
+
+    let mut chip8 = Chip8::with_seed(options.quirks, seed);
+    if let Err(err) = chip8.load(&rom, options.start) {
+        eprintln!("error: could not load ROM '{}': {}", options.rom, err);
+        process::exit(1);
+    }
+
+    let mut sound = match open_sound(&options) {
+        Ok(sound) => sound,
+        Err(err) => {
+            eprintln!("error: could not open audio output: {}", err);
+            process::exit(1);
+        }
+    };
+
+    let result = if options.headless {
+        headless(&mut chip8, &options, &mut movie, &mut sound)
+    } else {
+        run(&mut chip8, &options, &mut movie, &mut sound)
+    };
+    if let Some(sound) = &mut sound {
+        if let Err(err) = sound.backend.finish() {
+            eprintln!("error: could not finish audio output: {}", err);
+        }
+    }
+    if let (Some(path), Some(MovieMode::Record(recording))) = (&options.record, &movie) {
+        if let Err(err) = fs::write(path, recording.to_string()) {
+            eprintln!("error: could not write movie '{}': {}", path, err);
+            process::exit(1);
+        }
+    }
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+// Square wave generator and where its samples go, with --audio or --wav
+struct Sound {
+    tone: Tone,
+    backend: Box<dyn AudioBackend>,
+}
+
+fn open_sound(options: &Options) -> io::Result<Option<Sound>> {
+    let backend: Box<dyn AudioBackend> = match &options.audio {
+        Some(AudioOutput::Aplay) => Box::new(Aplay::spawn()?),
+        Some(AudioOutput::Wav(path)) => {
+            Box::new(WavWriter::new(BufWriter::new(File::create(path)?))?)
+        }
+        None => return Ok(None),
+    };
+    let tone = Tone::new(options.beep_frequency, options.volume);
+    Ok(Some(Sound { tone, backend }))
+}
+
+// Movie being recorded with --record or played back with --play
+enum MovieMode {
+    Record(Movie),
+    Play(Movie),
+}
+
+fn read_rom(path: &str) -> Vec<u8> {
+    match fs::read(path) {
+        Ok(rom) => rom,
+        Err(err) => {
+            eprintln!("error: could not read ROM '{}': {}", path, err);
+            process::exit(1);
+        }
+    }
+}
+
+// An empty script when no path is given
+fn read_keys(path: Option<&str>) -> Result<KeyScript, String> {
+    let Some(path) = path else {
+        return Ok(KeyScript::default());
+    };
+    fs::read_to_string(path)
+        .map_err(|err| err.to_string())
+        .and_then(|text| KeyScript::parse(&text))
+        .map_err(|err| format!("could not read key script '{}': {}", path, err))
+}
+
+// Returns the exit status, 0 when every line of the log matched
+fn compare(options: &CompareOptions) -> i32 {
+    let rom = read_rom(&options.rom);
+    let log = match fs::read_to_string(&options.log) {
+        Ok(log) => log,
+        Err(err) => {
+            eprintln!("error: could not read log '{}': {}", options.log, err);
+            return 1;
+        }
+    };
+    let keys = match read_keys(options.keys.as_deref()) {
+        Ok(keys) => keys,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return 1;
+        }
+    };
+
+    let mut chip8 = Chip8::with_seed(options.quirks, options.seed);
+    if let Err(err) = chip8.load(&rom, options.start) {
+        eprintln!("error: could not load ROM '{}': {}", options.rom, err);
+        return 1;
+    }
+    match compare::compare(&mut chip8, &log, &keys, options.cycles_per_frame) {
+        Ok(Comparison::Matched { lines }) => {
+            println!("all {} lines matched", lines);
+            0
+        }
+        Ok(Comparison::Diverged(divergence)) => {
+            print!("{}", divergence);
+            1
+        }
+        Ok(Comparison::Stopped {
+            line,
+            cycle,
+            reason,
+        }) => {
+            println!("stopped before line {} (cycle {}): {}", line, cycle, reason);
+            1
+        }
+        Err(err) => {
+            eprintln!("error: bad log '{}': {}", options.log, err);
+            1
+        }
+    }
+}
+
+fn run(
+    chip8: &mut Chip8,
+    options: &Options,
+    movie: &mut Option<MovieMode>,
+    sound: &mut Option<Sound>,
+) -> Result<(), Box<dyn Error>> {
+    // Setup std in for keyboard input, restored when dropped
+    let mut terminal = RawTerminal::enable()?;
+    let mut keypad = Keypad::new(keypad::RELEASE_TIMEOUT);
+    let mut renderer: Box<dyn Renderer> = match options.renderer {
+        RendererKind::Block => {
+            Box::new(TerminalRenderer::half_blocks(options.scale, options.theme))
+        }
+        RendererKind::Full => Box::new(TerminalRenderer::full_rows(
+            options.scale,
+            '█',
+            options.theme,
+        )),
+        RendererKind::Ascii => Box::new(TerminalRenderer::full_rows(
+            options.scale,
+            '#',
+            options.theme,
+        )),
+        RendererKind::None => Box::new(NullRenderer),
+    };
+
+    let frame_time = Duration::from_secs_f64(1.0 / cli::FRAME_RATE);
+    let mut slot = 5;
+    let mut rewind = Rewind::new(options.rewind_seconds as usize * cli::FRAME_RATE as usize);
+    // Created when first breaking into the debugger, or up front with --debug
+    let mut debugger = None;
+    let mut pause = options
+        .debug
+        .then(|| String::from("paused, type help for commands"));
+    let mut trace = open_trace(options)?;
+    let mut frame = 0;
 
     loop {
+        if let Some(output) = pause.take() {
+            let debugger = debugger.get_or_insert_with(Debugger::new);
+            let mut step_fn = |chip8: &mut Chip8| step(chip8, &mut trace);
+            let resume = console::prompt(
+                chip8,
+                debugger,
+                &mut step_fn,
+                &mut terminal,
+                renderer.as_mut(),
+                output,
+            )?;
+            write_trace(&mut trace)?;
+            match resume {
+                Resume::Run => renderer.reset(),
+                Resume::Quit => return Ok(()),
+            }
+        }
+
         let start_time = Instant::now();
- 
-        chip8.emulate();
-        
-        reader.read_exact(&mut buffer).unwrap();
+
+        keypad.feed(&terminal.read_available()?, start_time);
+        if keypad.quit_requested() {
+            return Ok(());
+        }
+        for hotkey in keypad.take_hotkeys() {
+            // Breaking part way through a frame or jumping to another point
+            // in time would break the movie
+            if hotkey == Hotkey::Break && movie.is_some() {
+                renderer.message("Can't debug with a movie");
+                continue;
+            }
+            if hotkey == Hotkey::Break {
+                pause = Some(String::from("break"));
+                continue;
+            }
+            if hotkey == Hotkey::LoadState && movie.is_some() {
+                renderer.message("Can't load states with a movie");
+                continue;
+            }
+            let message = handle_hotkey(chip8, options, &mut slot, &mut rewind, hotkey);
+            renderer.message(&message);
+        }
+        if pause.is_some() {
+            continue;
+        }
+        // Keys come from the movie while it plays, recordings take them from the keyboard
+        let playing = match movie {
+            Some(MovieMode::Play(playback)) => playback.play(frame, chip8),
+            _ => false,
+        };
+        if !playing {
+            keypad.update(chip8, start_time);
+        }
+        match movie {
+            Some(MovieMode::Record(recording)) => recording.record(chip8),
+            Some(MovieMode::Play(_)) if !playing => {
+                renderer.message("Movie finished");
+                *movie = None;
+            }
+            _ => {}
+        }
+        frame += 1;
+
+        let rewinding = movie.is_none() && keypad.rewind_held(start_time);
+        if let Some(sound) = sound.as_mut().filter(|_| !rewinding) {
+            sound.backend.write(&sound.tone.frame(chip8))?;
+        }
+
+        if rewinding {
+            rewind.step_back(chip8)?;
+            renderer.render(chip8)?;
+            chip8.clear_display_dirty();
+        } else if let Some(debugger) = debugger.as_mut() {
+            let result = debugger.run_frame_with(chip8, options.cycles_per_frame, &mut |chip8| {
+                step(chip8, &mut trace)
+            });
+            write_trace(&mut trace)?;
+            renderer.render(chip8)?;
+            chip8.clear_display_dirty();
+            match result {
+                Ok(()) => rewind.push(chip8),
+                // Exits as it would without the debugger
+                Err(StopReason::Exit) => return Ok(()),
+                Err(reason) => pause = Some(reason.to_string()),
+            }
+        } else {
+            let result = match trace.as_mut() {
+                Some(trace) => trace.tracer.run_frame(chip8, options.cycles_per_frame),
+                None => chip8.run_frame(options.cycles_per_frame),
+            };
+            write_trace(&mut trace)?;
+            renderer.render(chip8)?;
+            chip8.clear_display_dirty();
+            if result? == StepOutcome::Exit {
+                return Ok(());
+            }
+            rewind.push(chip8);
+        }
 
         let elapsed_time = start_time.elapsed();
-        if elapsed_time < emu_speed {
-            thread::sleep(emu_speed - elapsed_time);
+        if elapsed_time < frame_time {
+            thread::sleep(frame_time - elapsed_time);
+        }
+    }
+}
+
+// Run --frames frames as fast as possible with scripted keys, then report the display
+fn headless(
+    chip8: &mut Chip8,
+    options: &Options,
+    movie: &mut Option<MovieMode>,
+    sound: &mut Option<Sound>,
+) -> Result<(), Box<dyn Error>> {
+    let keys = read_keys(options.keys.as_deref())?;
+    let mut trace = open_trace(options)?;
+    let frames = options.frames.unwrap_or(0);
+    let mut frame = 0;
+    while frame < frames {
+        match movie {
+            Some(MovieMode::Play(playback)) => {
+                if !playback.play(frame as usize, chip8) {
+                    *movie = None;
+                }
+            }
+            _ => keys.apply(frame, chip8),
+        }
+        if let Some(MovieMode::Record(recording)) = movie {
+            recording.record(chip8);
+        }
+        if let Some(sound) = sound {
+            sound.backend.write(&sound.tone.frame(chip8))?;
+        }
+        frame += 1;
+
+        let result = match trace.as_mut() {
+            Some(trace) => trace.tracer.run_frame(chip8, options.cycles_per_frame),
+            None => chip8.run_frame(options.cycles_per_frame),
+        };
+        write_trace(&mut trace)?;
+        if result? == StepOutcome::Exit {
+            break;
+        }
+    }
+
+    if let Some((path, format)) = &options.screenshot {
+        fs::write(path, format.encode(chip8.framebuffer()))
+            .map_err(|err| format!("could not write screenshot '{}': {}", path, err))?;
+    }
+    println!("frames={} fb={:08X}", frame, chip8.framebuffer().hash());
+    Ok(())
+}
+
+// Tracer for --trace and the file its lines go to
+struct Trace {
+    tracer: Tracer,
+    file: BufWriter<File>,
+}
+
+fn open_trace(options: &Options) -> io::Result<Option<Trace>> {
+    let Some(path) = &options.trace else {
+        return Ok(None);
+    };
+    Ok(Some(Trace {
+        tracer: Tracer::new(options.trace_filter.clone()),
+        file: BufWriter::new(File::create(path)?),
+    }))
+}
+
+// Execute one instruction, through the tracer when tracing
+fn step(chip8: &mut Chip8, trace: &mut Option<Trace>) -> Result<StepOutcome, Chip8Error> {
+    match trace {
+        Some(trace) => trace.tracer.step(chip8),
+        None => chip8.step(),
+    }
+}
+
+fn write_trace(trace: &mut Option<Trace>) -> io::Result<()> {
+    if let Some(trace) = trace {
+        for line in trace.tracer.take_lines() {
+            writeln!(trace.file, "{}", line)?;
+        }
+        // Keep the file complete up to the last frame if the process is killed
+        trace.file.flush()?;
+    }
+    Ok(())
+}
+
+// Save states live next to the rom, e.g. game.ch8.state5
+fn state_path(options: &Options, slot: u8) -> String {
+    format!("{}.state{}", options.rom, slot)
+}
+
+// Returns a message describing what happened
+fn handle_hotkey(
+    chip8: &mut Chip8,
+    options: &Options,
+    slot: &mut u8,
+    rewind: &mut Rewind,
+    hotkey: Hotkey,
+) -> String {
+    match hotkey {
+        Hotkey::SelectSlot(new_slot) => {
+            *slot = new_slot;
+            format!("Slot {}", slot)
+        }
+        Hotkey::SaveState => match fs::write(state_path(options, *slot), chip8.save_state()) {
+            Ok(()) => format!("Saved slot {}", slot),
+            Err(err) => format!("Save failed: {}", err),
+        },
+        // Handled by the main loop
+        Hotkey::Break => String::new(),
+        Hotkey::LoadState => {
+            let result = fs::read(state_path(options, *slot))
+                .map_err(|err| err.to_string())
+                .and_then(|state| chip8.load_state(&state).map_err(|err| err.to_string()));
+            match result {
+                Ok(()) => {
+                    // The history leads up to the old state, not the loaded one
+                    rewind.clear();
+                    rewind.push(chip8);
+                    format!("Loaded slot {}", slot)
+                }
+                Err(err) => format!("Load failed: {}", err),
+            }
         }
-        
     }
-   
-    // Reset terminal to orinal config - unreachable until input for stopping emulation
-    // tcsetattr(stdin, TCSANOW, &original_term).unwrap();
 }
@@ -0,0 +1,227 @@
+use crate::chip::Chip8;
+use crate::hash;
+use crate::quirks::Quirks;
+use std::fmt;
+
+/// First line of every movie file, with the format version.
+const HEADER: &str = "chip8emu movie 1";
+/// Longest movie that can be loaded, 24 hours at 60 frames a second.
+pub const MAX_FRAMES: usize = 24 * 60 * 60 * 60;
+
+/// Keypad state for every frame of a session, plus everything else needed
+/// to replay it exactly. Saved as text:
+///
+/// ```text
+/// chip8emu movie 1
+/// rom 4A1C02F3
+/// quirks schip
+/// seed 42
+/// cycles-per-frame 10
+/// start 0x200
+/// frames
+/// 0000 120
+/// 0010 3
+/// ```
+///
+/// `rom` is the FNV-1a hash of the ROM. After `frames` each line is a mask of
+/// the keys held, bit n for key n, and how many frames in a row it was held.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_hash: u32,
+    /// Always one of the presets, saved by name.
+    pub quirks: Quirks,
+    pub seed: u64,
+    pub cycles_per_frame: u32,
+    pub start: u16,
+    frames: Vec<u16>,
+}
+
+impl Movie {
+    /// An empty movie for a session about to start. Fails if `quirks` is
+    /// not one of the presets, only presets can be saved.
+    pub fn new(
+        rom: &[u8],
+        quirks: Quirks,
+        seed: u64,
+        cycles_per_frame: u32,
+        start: u16,
+    ) -> Result<Self, String> {
+        if quirks.name().is_none() {
+            return Err(String::from("the quirks are not one of the profiles"));
+        }
+        Ok(Movie {
+            rom_hash: hash::fnv1a(rom),
+            quirks,
+            seed,
+            cycles_per_frame,
+            start,
+            frames: Vec::new(),
+        })
+    }
+
+    pub fn matches_rom(&self, rom: &[u8]) -> bool {
+        self.rom_hash == hash::fnv1a(rom)
+    }
+
+    /// Number of frames recorded.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Record the keys held for the next frame, call before running it.
+    pub fn record(&mut self, chip8: &Chip8) {
+        let mask = chip8
+            .keys()
+            .iter()
+            .enumerate()
+            .fold(0, |mask, (key, &pressed)| mask | (pressed as u16) << key);
+        self.frames.push(mask);
+    }
+
+    /// Press and release keys to match `frame`, returning false once past the end.
+    pub fn play(&self, frame: usize, chip8: &mut Chip8) -> bool {
+        let Some(&mask) = self.frames.get(frame) else {
+            return false;
+        };
+        for key in 0..16 {
+            chip8.set_key(key, mask & (1 << key) != 0);
+        }
+        true
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()));
+        if lines.next().map(|(_, line)| line) != Some(HEADER) {
+            return Err(format!(
+                "not a movie, expected '{}' on the first line",
+                HEADER
+            ));
+        }
+
+        let (mut rom_hash, mut quirks, mut seed, mut cycles_per_frame, mut start) =
+            (None, None, None, None, None);
+        for (number, line) in lines.by_ref() {
+            if line == "frames" {
+                break;
+            }
+            let error = || format!("line {}: bad header field '{}'", number, line);
+            let (key, value) = line.split_once(' ').ok_or_else(error)?;
+            match key {
+                "rom" => rom_hash = Some(u32::from_str_radix(value, 16).map_err(|_| error())?),
+                "quirks" => quirks = Some(Quirks::from_name(value).ok_or_else(error)?),
+                "seed" => seed = Some(value.parse().map_err(|_| error())?),
+                "cycles-per-frame" => cycles_per_frame = Some(value.parse().map_err(|_| error())?),
+                "start" => {
+                    let hex = value.strip_prefix("0x").ok_or_else(error)?;
+                    start = Some(u16::from_str_radix(hex, 16).map_err(|_| error())?);
+                }
+                _ => return Err(error()),
+            }
+        }
+        let missing = |name: &str| format!("header is missing '{}'", name);
+        let mut movie = Movie {
+            rom_hash: rom_hash.ok_or_else(|| missing("rom"))?,
+            quirks: quirks.ok_or_else(|| missing("quirks"))?,
+            seed: seed.ok_or_else(|| missing("seed"))?,
+            cycles_per_frame: cycles_per_frame.ok_or_else(|| missing("cycles-per-frame"))?,
+            start: start.ok_or_else(|| missing("start"))?,
+            frames: Vec::new(),
+        };
+
+        for (number, line) in lines {
+            if line.is_empty() {
+                continue;
+            }
+            let error = || {
+                format!(
+                    "line {}: expected '<keys> <frames>', got '{}'",
+                    number, line
+                )
+            };
+            let (mask, count) = line.split_once(' ').ok_or_else(error)?;
+            let mask = u16::from_str_radix(mask, 16).map_err(|_| error())?;
+            let count: usize = count.parse().map_err(|_| error())?;
+            if count > MAX_FRAMES - movie.frames.len() {
+                return Err(format!(
+                    "line {}: movie is longer than {} frames",
+                    number, MAX_FRAMES
+                ));
+            }
+            movie.frames.extend(std::iter::repeat_n(mask, count));
+        }
+        Ok(movie)
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        writeln!(f, "rom {:08X}", self.rom_hash)?;
+        writeln!(f, "quirks {}", self.quirks.name().unwrap_or_default())?;
+        writeln!(f, "seed {}", self.seed)?;
+        writeln!(f, "cycles-per-frame {}", self.cycles_per_frame)?;
+        writeln!(f, "start 0x{:X}", self.start)?;
+        writeln!(f, "frames")?;
+        // Runs of frames with the same keys held share a line
+        for run in self.frames.chunk_by(|a, b| a == b) {
+            writeln!(f, "{:04X} {}", run[0], run.len())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER_FIELDS: &str = "chip8emu movie 1\nrom 00000000\nquirks schip\nseed 42\n\
+                                 cycles-per-frame 10\nstart 0x200\nframes\n";
+
+    #[test]
+    fn saved_movies_load_back() {
+        let mut chip8 = Chip8::with_seed(Quirks::superchip(), 42);
+        let mut movie = Movie::new(&[0x12, 0x00], Quirks::superchip(), 42, 10, 0x200).unwrap();
+        for key in [None, None, Some(5), Some(5), None] {
+            if let Some(key) = key {
+                chip8.set_key(key, true);
+            } else {
+                (0..16).for_each(|key| chip8.set_key(key, false));
+            }
+            movie.record(&chip8);
+        }
+        let text = movie.to_string();
+        assert!(
+            text.ends_with("frames\n0000 2\n0020 2\n0000 1\n"),
+            "{}",
+            text
+        );
+        assert_eq!(Movie::parse(&text), Ok(movie));
+    }
+
+    #[test]
+    fn only_preset_quirks_can_be_recorded() {
+        let quirks = Quirks {
+            clipping: false,
+            ..Quirks::superchip()
+        };
+        assert!(Movie::new(&[], quirks, 0, 10, 0x200).is_err());
+    }
+
+    #[test]
+    fn runs_past_the_longest_movie_are_rejected() {
+        let text = format!("{}0001 {}\n", HEADER_FIELDS, MAX_FRAMES);
+        assert_eq!(Movie::parse(&text).map(|movie| movie.len()), Ok(MAX_FRAMES));
+
+        let text = format!("{}0001 {}\n0002 1\n", HEADER_FIELDS, MAX_FRAMES);
+        assert!(Movie::parse(&text).is_err());
+        let text = format!("{}0001 {}\n", HEADER_FIELDS, usize::MAX);
+        assert!(Movie::parse(&text).is_err());
+    }
+}
@@ -0,0 +1,105 @@
+/// How LD [I], Vx and LD Vx, [I] leave the index register afterwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexIncrement {
+    Unchanged,
+    ByX,
+    ByXPlusOne,
+}
+
+/// Behaviours that differ between the CHIP-8 platforms. See
+/// https://github.com/Timendus/chip8-test-suite#quirks-test for what each one does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// OR, AND and XOR reset VF to 0
+    pub vf_reset: bool,
+    /// Index register after storing or loading registers
+    pub memory: IndexIncrement,
+    /// DRW waits for the next 60hz frame, at most one sprite per frame
+    pub display_wait: bool,
+    /// Sprites are clipped at the screen edges rather than wrapping around
+    pub clipping: bool,
+    /// SHR and SHL shift Vx in place instead of shifting Vy into Vx
+    pub shifting: bool,
+    /// JP V0, addr jumps to addr + Vx, x being the high nibble of addr
+    pub jumping: bool,
+    /// Bytes of addressable memory, accesses past it fault
+    pub memory_size: usize,
+}
+
+impl Quirks {
+    /// Original COSMAC VIP interpreter
+    pub fn chip8() -> Self {
+        Quirks {
+            vf_reset: true,
+            memory: IndexIncrement::ByXPlusOne,
+            display_wait: true,
+            clipping: true,
+            shifting: false,
+            jumping: false,
+            memory_size: 0x1000,
+        }
+    }
+
+    /// CHIP-48 on the HP48 calculators
+    pub fn chip48() -> Self {
+        Quirks {
+            vf_reset: false,
+            memory: IndexIncrement::ByX,
+            display_wait: false,
+            clipping: true,
+            shifting: true,
+            jumping: true,
+            memory_size: 0x1000,
+        }
+    }
+
+    /// SUPER-CHIP 1.1
+    pub fn superchip() -> Self {
+        Quirks {
+            vf_reset: false,
+            memory: IndexIncrement::Unchanged,
+            display_wait: false,
+            clipping: true,
+            shifting: true,
+            jumping: true,
+            memory_size: 0x1000,
+        }
+    }
+
+    /// XO-CHIP as implemented by Octo
+    pub fn xochip() -> Self {
+        Quirks {
+            vf_reset: false,
+            memory: IndexIncrement::ByXPlusOne,
+            display_wait: false,
+            clipping: false,
+            shifting: false,
+            jumping: false,
+            memory_size: 0x10000,
+        }
+    }
+
+    /// Look up a preset by name: chip8, chip48, schip or xochip.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "chip8" => Some(Quirks::chip8()),
+            "chip48" => Some(Quirks::chip48()),
+            "schip" => Some(Quirks::superchip()),
+            "xochip" => Some(Quirks::xochip()),
+            _ => None,
+        }
+    }
+
+    /// Name of the preset these quirks are, None if they have been customised.
+    pub fn name(&self) -> Option<&'static str> {
+        ["chip8", "chip48", "schip", "xochip"]
+            .into_iter()
+            .find(|name| Quirks::from_name(name) == Some(*self))
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::chip8()
+    }
+}
@@ -0,0 +1,272 @@
+use chip8emu::{Chip8, Framebuffer};
+use std::io::{self, Write};
+
+const ESC: char = 27 as char;
+
+/// Presents the emulator's display, called once per frame.
+pub trait Renderer {
+    fn render(&mut self, chip8: &Chip8) -> io::Result<()>;
+
+    /// Show a one line notice to the user, such as a save state being written.
+    fn message(&mut self, _text: &str) {}
+
+    /// Terminal lines in use, anything else can be written below them.
+    fn lines(&self) -> usize {
+        0
+    }
+
+    /// Forget what is on screen so the next frame is drawn in full.
+    fn reset(&mut self) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Ansi256(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    // SGR parameters, `base` being 38 for foreground or 48 for background
+    fn sgr(self, base: u8) -> String {
+        match self {
+            Color::Ansi256(n) => format!("{};5;{}", base, n),
+            Color::Rgb(r, g, b) => format!("{};2;{};{};{}", base, r, g, b),
+        }
+    }
+}
+
+/// Colours for each combination of XO-CHIP planes: off, plane 1, plane 2 and both.
+/// The monochrome theme leaves the terminal's own colours alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    palette: Option<[Color; 4]>,
+}
+
+impl Theme {
+    pub fn from_name(name: &str) -> Option<Self> {
+        let palette = match name {
+            "mono" => None,
+            "green" => Some([
+                Color::Ansi256(16),
+                Color::Ansi256(46),
+                Color::Ansi256(28),
+                Color::Ansi256(120),
+            ]),
+            "amber" => Some([
+                Color::Rgb(0x1A, 0x10, 0x00),
+                Color::Rgb(0xFF, 0xB0, 0x00),
+                Color::Rgb(0x99, 0x66, 0x00),
+                Color::Rgb(0xFF, 0xE0, 0x80),
+            ]),
+            // Octo's default palette
+            "octo" => Some([
+                Color::Rgb(0x99, 0x66, 0x00),
+                Color::Rgb(0xFF, 0xCC, 0x00),
+                Color::Rgb(0xFF, 0x66, 0x00),
+                Color::Rgb(0x66, 0x22, 0x00),
+            ]),
+            _ => return None,
+        };
+        Some(Theme { palette })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cell {
+    glyph: char,
+    fg: Option<Color>,
+    bg: Option<Color>,
+}
+
+/// Draws to the terminal, only writing the cells that changed since the
+/// last frame. Half block characters fit two rows of pixels in each line.
+pub struct TerminalRenderer {
+    scale: usize,
+    half_blocks: bool,
+    pixel: char,
+    theme: Theme,
+    // Cells on screen, empty until the first frame or after a resolution change
+    previous: Vec<Cell>,
+    columns: usize,
+    status: Vec<String>,
+    message: String,
+}
+
+impl TerminalRenderer {
+    /// Half block renderer, two rows of pixels per line.
+    pub fn half_blocks(scale: usize, theme: Theme) -> Self {
+        TerminalRenderer::new(scale, true, '█', theme)
+    }
+
+    /// One row of pixels per line, drawn with `pixel`.
+    pub fn full_rows(scale: usize, pixel: char, theme: Theme) -> Self {
+        TerminalRenderer::new(scale, false, pixel, theme)
+    }
+
+    fn new(scale: usize, half_blocks: bool, pixel: char, theme: Theme) -> Self {
+        TerminalRenderer {
+            scale,
+            half_blocks,
+            pixel,
+            theme,
+            previous: Vec::new(),
+            columns: 0,
+            status: Vec::new(),
+            message: String::new(),
+        }
+    }
+
+    fn cell(&self, top: u8, bottom: Option<u8>) -> Cell {
+        match self.theme.palette {
+            // Upper half block coloured by the top pixel over the bottom pixel
+            Some(palette) => Cell {
+                glyph: if bottom.is_some() { '▀' } else { ' ' },
+                fg: Some(palette[top as usize & 0x3]),
+                bg: Some(palette[bottom.unwrap_or(top) as usize & 0x3]),
+            },
+            None => Cell {
+                glyph: match (top != 0, bottom.is_some_and(|bottom| bottom != 0)) {
+                    (false, false) => ' ',
+                    (true, false) if bottom.is_some() => '▀',
+                    (true, false) => self.pixel,
+                    (false, true) => '▄',
+                    (true, true) => '█',
+                },
+                fg: None,
+                bg: None,
+            },
+        }
+    }
+
+    fn cells(&self, framebuffer: &Framebuffer) -> Vec<Cell> {
+        let rows: Vec<&[u8]> = framebuffer.rows().collect();
+        let mut cells = Vec::new();
+        if self.half_blocks {
+            for pair in rows.chunks(2) {
+                for x in 0..framebuffer.width() {
+                    cells.push(self.cell(pair[0][x], pair.get(1).map(|row| row[x])));
+                }
+            }
+        } else {
+            for row in rows {
+                cells.extend(row.iter().map(|&pixel| self.cell(pixel, None)));
+            }
+        }
+        cells
+    }
+
+    fn draw_cells(&mut self, out: &mut impl Write, cells: Vec<Cell>) -> io::Result<()> {
+        // Cursor position after the last write, saves a move between neighbouring cells
+        let mut cursor = None;
+        let mut colors = (None, None);
+        for (i, cell) in cells.iter().enumerate() {
+            if self.previous.get(i) == Some(cell) {
+                continue;
+            }
+            let (row, col) = (i / self.columns, i % self.columns);
+            if cursor != Some(i) {
+                write!(out, "{}[{};{}H", ESC, row + 1, col * self.scale + 1)?;
+            }
+            if (cell.fg, cell.bg) != colors {
+                write!(out, "{}[0", ESC)?;
+                if let Some(fg) = cell.fg {
+                    write!(out, ";{}", fg.sgr(38))?;
+                }
+                if let Some(bg) = cell.bg {
+                    write!(out, ";{}", bg.sgr(48))?;
+                }
+                write!(out, "m")?;
+                colors = (cell.fg, cell.bg);
+            }
+            for _ in 0..self.scale {
+                write!(out, "{}", cell.glyph)?;
+            }
+            cursor = Some(i + 1).filter(|next| next % self.columns != 0);
+        }
+        if colors != (None, None) {
+            write!(out, "{}[0m", ESC)?;
+        }
+        self.previous = cells;
+        Ok(())
+    }
+
+    // Program counter, current op code and index register next to the screen
+    fn draw_status(&mut self, out: &mut impl Write, chip8: &Chip8) -> io::Result<()> {
+        let pc = chip8.pc() as usize;
+        let memory = chip8.memory();
+        let opcode = match (memory.get(pc), memory.get(pc + 1)) {
+            (Some(&high), Some(&low)) => (high as u16) << 8 | low as u16,
+            _ => 0,
+        };
+        let status = vec![
+            format!("PC:0x{:0>3X}", pc),
+            format!("Op:0x{:0>4X}", opcode),
+            format!("Index:0x{:0>4X}", chip8.index()),
+            String::new(),
+            self.message.clone(),
+        ];
+        let col = self.columns * self.scale + 3;
+        for (row, line) in status.iter().enumerate() {
+            if self.status.get(row) != Some(line) {
+                write!(out, "{}[{};{}H{}{}[K", ESC, row + 1, col, line, ESC)?;
+            }
+        }
+        self.status = status;
+        Ok(())
+    }
+}
+
+impl Renderer for TerminalRenderer {
+    fn render(&mut self, chip8: &Chip8) -> io::Result<()> {
+        let framebuffer = chip8.framebuffer();
+        if self.columns != framebuffer.width() {
+            // First frame or resolution change, start from a blank screen
+            self.previous.clear();
+            self.status.clear();
+            self.columns = framebuffer.width();
+        }
+        let redraw = self.previous.is_empty() || chip8.display_dirty();
+
+        let mut out = io::stdout().lock();
+        if self.previous.is_empty() {
+            // Ansi clear screen and hide the cursor
+            write!(out, "{}[2J{}[?25l", ESC, ESC)?;
+        }
+        if redraw {
+            let cells = self.cells(framebuffer);
+            self.draw_cells(&mut out, cells)?;
+        }
+        self.draw_status(&mut out, chip8)?;
+        out.flush()
+    }
+
+    fn message(&mut self, text: &str) {
+        self.message = text.to_string();
+    }
+
+    fn lines(&self) -> usize {
+        (self.previous.len() / self.columns.max(1)).max(self.status.len())
+    }
+
+    fn reset(&mut self) {
+        self.previous.clear();
+        self.status.clear();
+    }
+}
+
+impl Drop for TerminalRenderer {
+    fn drop(&mut self) {
+        // Leave the cursor visible underneath the screen
+        print!("{}[0m{}[{};1H{}[?25h", ESC, ESC, self.lines() + 1, ESC);
+        let _ = io::stdout().flush();
+    }
+}
+
+/// Draws nothing, for running without a display.
+pub struct NullRenderer;
+
+impl Renderer for NullRenderer {
+    fn render(&mut self, _chip8: &Chip8) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+use crate::chip::Chip8;
+use crate::state::StateError;
+use std::collections::VecDeque;
+
+// How to get from one snapshot back to the one before it
+enum Undo {
+    // Offsets into the save state and the byte they held in the older snapshot
+    Delta(Vec<(u32, u8)>),
+    // Used when the state length changed, which a delta can't describe
+    Full(Vec<u8>),
+}
+
+/// Bounded history of `Chip8` snapshots for rewinding, normally one per frame.
+///
+/// Only the newest snapshot is kept whole. Every older one is stored as the
+/// bytes that differ from its successor, so frames that touch little memory
+/// cost little.
+pub struct Rewind {
+    capacity: usize,
+    latest: Vec<u8>,
+    undo: VecDeque<Undo>,
+}
+
+impl Rewind {
+    /// Keep at most `capacity` snapshots, dropping the oldest.
+    pub fn new(capacity: usize) -> Self {
+        Rewind {
+            capacity,
+            latest: Vec::new(),
+            undo: VecDeque::new(),
+        }
+    }
+
+    /// Number of snapshots held.
+    pub fn len(&self) -> usize {
+        if self.latest.is_empty() {
+            0
+        } else {
+            self.undo.len() + 1
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.latest.clear();
+        self.undo.clear();
+    }
+
+    /// Record the current state as the newest snapshot.
+    pub fn push(&mut self, chip8: &Chip8) {
+        if self.capacity == 0 {
+            return;
+        }
+        let state = chip8.save_state();
+        if !self.latest.is_empty() {
+            let undo = if state.len() == self.latest.len() {
+                let delta = state
+                    .iter()
+                    .zip(&self.latest)
+                    .enumerate()
+                    .filter(|(_, (new, old))| new != old)
+                    .map(|(offset, (_, &old))| (offset as u32, old))
+                    .collect();
+                Undo::Delta(delta)
+            } else {
+                Undo::Full(std::mem::take(&mut self.latest))
+            };
+            self.undo.push_back(undo);
+            if self.undo.len() >= self.capacity {
+                self.undo.pop_front();
+            }
+        }
+        self.latest = state;
+    }
+
+    /// Step back to the previous snapshot and load it into `chip8`. Returns
+    /// false once the oldest snapshot has been reached.
+    pub fn step_back(&mut self, chip8: &mut Chip8) -> Result<bool, StateError> {
+        let undo = match self.undo.pop_back() {
+            Some(undo) => undo,
+            None => return Ok(false),
+        };
+        match undo {
+            Undo::Delta(delta) => {
+                for (offset, old) in delta {
+                    self.latest[offset as usize] = old;
+                }
+            }
+            Undo::Full(state) => self.latest = state,
+        }
+        chip8.load_state(&self.latest)?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+
+    // A machine running ADD V0, 1 forever, so every step changes the state
+    fn machine() -> Chip8 {
+        let mut chip8 = Chip8::with_seed(Quirks::xochip(), 0);
+        chip8.load(&[0x70, 0x01, 0x12, 0x00], 0x200).unwrap();
+        chip8
+    }
+
+    // Step until V0 has gone up by one
+    fn advance(chip8: &mut Chip8) {
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+    }
+
+    #[test]
+    fn step_back_restores_the_exact_state() {
+        let mut chip8 = machine();
+        let mut rewind = Rewind::new(10);
+        let mut states = Vec::new();
+        for _ in 0..4 {
+            rewind.push(&chip8);
+            states.push(chip8.save_state());
+            advance(&mut chip8);
+        }
+        states.pop();
+        while let Some(state) = states.pop() {
+            assert_eq!(rewind.step_back(&mut chip8), Ok(true));
+            assert_eq!(chip8.save_state(), state);
+        }
+        assert_eq!(chip8.registers()[0], 0);
+    }
+
+    #[test]
+    fn oldest_snapshots_are_dropped_at_capacity() {
+        let mut chip8 = machine();
+        let mut rewind = Rewind::new(3);
+        for _ in 0..6 {
+            rewind.push(&chip8);
+            advance(&mut chip8);
+        }
+        assert_eq!(rewind.len(), 3);
+        assert_eq!(rewind.step_back(&mut chip8), Ok(true));
+        assert_eq!(rewind.step_back(&mut chip8), Ok(true));
+        assert_eq!(rewind.step_back(&mut chip8), Ok(false));
+        // Pushed with V0 at 0 to 5, the last three kept
+        assert_eq!(chip8.registers()[0], 3);
+    }
+
+    #[test]
+    fn the_oldest_snapshot_survives_stepping_past_it() {
+        let mut chip8 = machine();
+        let mut rewind = Rewind::new(10);
+        rewind.push(&chip8);
+        let oldest = chip8.save_state();
+        advance(&mut chip8);
+        rewind.push(&chip8);
+        assert_eq!(rewind.step_back(&mut chip8), Ok(true));
+        assert_eq!(rewind.step_back(&mut chip8), Ok(false));
+        assert_eq!(chip8.save_state(), oldest);
+        assert_eq!(rewind.len(), 1);
+        // Still usable as the base for new snapshots
+        advance(&mut chip8);
+        rewind.push(&chip8);
+        assert_eq!(rewind.step_back(&mut chip8), Ok(true));
+        assert_eq!(chip8.save_state(), oldest);
+    }
+
+    #[test]
+    fn full_snapshots_when_the_state_length_changes() {
+        let mut chip8 = machine();
+        let mut rewind = Rewind::new(10);
+        rewind.push(&chip8);
+        let lores = chip8.save_state();
+        // HIGH, the hires display makes the state longer
+        chip8.load(&[0x00, 0xFF], 0x300).unwrap();
+        chip8.step().unwrap();
+        rewind.push(&chip8);
+        assert_ne!(chip8.save_state().len(), lores.len());
+        assert!(matches!(rewind.undo.back(), Some(Undo::Full(_))));
+        assert_eq!(rewind.step_back(&mut chip8), Ok(true));
+        assert_eq!(chip8.save_state(), lores);
+    }
+
+    #[test]
+    fn nothing_is_kept_without_capacity() {
+        let mut rewind = Rewind::new(0);
+        rewind.push(&machine());
+        assert!(rewind.is_empty());
+    }
+}
@@ -0,0 +1,45 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+/// xorshift64* generator behind `RND Vx, byte`. The same seed always gives
+/// the same sequence, so runs can be replayed exactly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // Spread the seed with splitmix64, xorshift gets stuck on a zero state
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        Rng {
+            state: if z == 0 { 1 } else { z },
+        }
+    }
+
+    /// Internal state, for save states.
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    /// Restore a generator from `state`, None for the invalid zero state.
+    pub fn from_state(state: u64) -> Option<Self> {
+        (state != 0).then_some(Rng { state })
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        // The high bits of xorshift64* are the best mixed
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+}
+
+/// A different seed every call, for when runs need not be reproducible.
+pub fn random_seed() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
@@ -0,0 +1,169 @@
+use crate::framebuffer::Framebuffer;
+use std::path::Path;
+
+// Grey levels for each combination of XO-CHIP planes
+const PALETTE: [[u8; 3]; 4] = [
+    [0x00, 0x00, 0x00],
+    [0xFF, 0xFF, 0xFF],
+    [0xAA, 0xAA, 0xAA],
+    [0x55, 0x55, 0x55],
+];
+
+/// Image formats a framebuffer can be saved as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Indexed colour, planes shown in shades of grey.
+    Png,
+    /// Black and white, any lit pixel is black.
+    Pbm,
+}
+
+impl Format {
+    /// Format for a file name's extension, None if it is not one of ours.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(Format::Png),
+            "pbm" => Some(Format::Pbm),
+            _ => None,
+        }
+    }
+
+    pub fn encode(self, display: &Framebuffer) -> Vec<u8> {
+        match self {
+            Format::Png => png(display),
+            Format::Pbm => pbm(display),
+        }
+    }
+}
+
+/// Binary PBM, eight pixels to a byte with the leftmost in the top bit.
+pub fn pbm(display: &Framebuffer) -> Vec<u8> {
+    let mut out = format!("P4\n{} {}\n", display.width(), display.height()).into_bytes();
+    for row in display.rows() {
+        for chunk in row.chunks(8) {
+            let byte = chunk.iter().enumerate().fold(0, |byte, (i, &pixel)| {
+                byte | ((pixel != 0) as u8) << (7 - i)
+            });
+            out.push(byte);
+        }
+    }
+    out
+}
+
+/// PNG with a four colour palette, one byte per pixel and uncompressed data.
+pub fn png(display: &Framebuffer) -> Vec<u8> {
+    let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(display.width() as u32).to_be_bytes());
+    header.extend_from_slice(&(display.height() as u32).to_be_bytes());
+    // 8 bit palette indices, default compression, filtering and no interlace
+    header.extend_from_slice(&[8, 3, 0, 0, 0]);
+    chunk(&mut out, b"IHDR", &header);
+    chunk(&mut out, b"PLTE", PALETTE.as_flattened());
+
+    // Every row starts with filter type 0, none
+    let mut pixels = Vec::with_capacity((display.width() + 1) * display.height());
+    for row in display.rows() {
+        pixels.push(0);
+        pixels.extend(row.iter().map(|&pixel| pixel & 0x3));
+    }
+    chunk(&mut out, b"IDAT", &zlib_stored(&pixels));
+    chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(kind.iter().chain(data));
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+// zlib stream of uncompressed deflate blocks, at most 65535 bytes each
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        out.push(blocks.peek().is_none() as u8);
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32<'a>(bytes: impl Iterator<Item = &'a u8>) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_of_an_empty_iend_chunk() {
+        assert_eq!(crc32(b"IEND".iter()), 0xAE42_6082);
+        assert_eq!(crc32(b"123456789".iter()), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn adler32_of_known_strings() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn pbm_packs_eight_pixels_a_byte() {
+        let mut display = Framebuffer::new(64, 32);
+        display.toggle(0, 0, 1);
+        display.toggle(9, 0, 1);
+        // Any plane counts as lit
+        display.toggle(63, 31, 2);
+        let pbm = pbm(&display);
+
+        let header = b"P4\n64 32\n";
+        assert_eq!(&pbm[..header.len()], header);
+        let rows = &pbm[header.len()..];
+        assert_eq!(rows.len(), 8 * 32);
+        let mut expected = vec![0; 8 * 32];
+        expected[0] = 0x80;
+        expected[1] = 0x40;
+        expected[8 * 32 - 1] = 0x01;
+        assert_eq!(rows, expected);
+    }
+
+    #[test]
+    fn png_ends_with_iend() {
+        let png = png(&Framebuffer::new(64, 32));
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[png.len() - 12..], b"\0\0\0\0IEND\xAE\x42\x60\x82");
+    }
+}
@@ -0,0 +1,43 @@
+use chip8emu::audio::{AudioBackend, SAMPLE_RATE};
+use std::io::{self, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+/// Plays samples on the sound card by piping them into ALSA's `aplay`.
+pub struct Aplay {
+    child: Child,
+    stdin: Option<ChildStdin>,
+}
+
+impl Aplay {
+    pub fn spawn() -> io::Result<Self> {
+        let mut child = Command::new("aplay")
+            .args(["-q", "-t", "raw", "-f", "S16_LE", "-c", "1", "-r"])
+            .arg(SAMPLE_RATE.to_string())
+            .stdin(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|err| io::Error::new(err.kind(), format!("could not start aplay: {}", err)))?;
+        let stdin = child.stdin.take();
+        Ok(Aplay { child, stdin })
+    }
+}
+
+impl AudioBackend for Aplay {
+    fn write(&mut self, samples: &[i16]) -> io::Result<()> {
+        let bytes: Vec<u8> = samples
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        match &mut self.stdin {
+            Some(stdin) => stdin.write_all(&bytes),
+            None => Ok(()),
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        // Closing the pipe lets aplay play what is buffered and exit
+        self.stdin = None;
+        self.child.wait()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,226 @@
+use std::error::Error;
+use std::fmt;
+
+/// First bytes of every save state.
+pub const MAGIC: &[u8; 4] = b"C8ST";
+/// Bumped whenever the layout written by `Chip8::save_state` changes.
+pub const VERSION: u16 = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    Invalid(&'static str),
+    /// Saved under other quirks, named if they were a preset.
+    QuirksMismatch(Option<&'static str>),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(
+                    f,
+                    "unsupported save state version {} (expected {})",
+                    version, VERSION
+                )
+            }
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Invalid(what) => write!(f, "save state has an invalid {}", what),
+            StateError::QuirksMismatch(Some(name)) => {
+                write!(f, "save state was made with the {} quirks", name)
+            }
+            StateError::QuirksMismatch(None) => {
+                write!(f, "save state was made with different quirks")
+            }
+        }
+    }
+}
+
+impl Error for StateError {}
+
+/// Appends little endian fields to a save state.
+#[derive(Default)]
+pub struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        let mut writer = StateWriter::default();
+        writer.bytes(MAGIC);
+        writer.u16(VERSION);
+        writer
+    }
+
+    pub fn u8(&mut self, val: u8) {
+        self.bytes.push(val);
+    }
+
+    pub fn bool(&mut self, val: bool) {
+        self.bytes.push(val as u8);
+    }
+
+    pub fn u16(&mut self, val: u16) {
+        self.bytes.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, val: u32) {
+        self.bytes.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, val: u64) {
+        self.bytes.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, val: &[u8]) {
+        self.bytes.extend_from_slice(val);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Reads back the fields written by `StateWriter`, in the same order.
+pub struct StateReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    /// Check the header, leaving the reader at the first field.
+    pub fn new(bytes: &'a [u8]) -> Result<Self, StateError> {
+        let mut reader = StateReader { bytes };
+        if reader
+            .bytes(MAGIC.len())
+            .map_err(|_| StateError::BadMagic)?
+            != MAGIC
+        {
+            return Err(StateError::BadMagic);
+        }
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        Ok(reader)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid("flag")),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.bytes.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    /// Fail if anything is left over after the last field.
+    pub fn finish(self) -> Result<(), StateError> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(StateError::Invalid("length"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written() -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.u8(0xAB);
+        writer.bool(true);
+        writer.u16(0x1234);
+        writer.u32(0xDEAD_BEEF);
+        writer.u64(u64::MAX - 1);
+        writer.bytes(b"xyz");
+        writer.finish()
+    }
+
+    #[test]
+    fn fields_read_back_in_order() {
+        let bytes = written();
+        let mut reader = StateReader::new(&bytes).unwrap();
+        assert_eq!(reader.u8(), Ok(0xAB));
+        assert_eq!(reader.bool(), Ok(true));
+        assert_eq!(reader.u16(), Ok(0x1234));
+        assert_eq!(reader.u32(), Ok(0xDEAD_BEEF));
+        assert_eq!(reader.u64(), Ok(u64::MAX - 1));
+        assert_eq!(reader.bytes(3), Ok(&b"xyz"[..]));
+        assert_eq!(reader.finish(), Ok(()));
+    }
+
+    #[test]
+    fn bad_magic_is_rejected() {
+        let mut bytes = written();
+        bytes[0] = b'X';
+        assert_eq!(StateReader::new(&bytes).err(), Some(StateError::BadMagic));
+        assert_eq!(StateReader::new(b"C8").err(), Some(StateError::BadMagic));
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let mut bytes = written();
+        bytes[4..6].copy_from_slice(&(VERSION - 1).to_le_bytes());
+        assert_eq!(
+            StateReader::new(&bytes).err(),
+            Some(StateError::UnsupportedVersion(VERSION - 1))
+        );
+    }
+
+    #[test]
+    fn truncated_states_are_rejected() {
+        let bytes = written();
+        assert_eq!(
+            StateReader::new(&bytes[..5]).err(),
+            Some(StateError::Truncated)
+        );
+        let mut reader = StateReader::new(&bytes[..bytes.len() - 1]).unwrap();
+        reader.u8().unwrap();
+        reader.bool().unwrap();
+        reader.u16().unwrap();
+        reader.u32().unwrap();
+        reader.u64().unwrap();
+        assert_eq!(reader.bytes(3), Err(StateError::Truncated));
+    }
+
+    #[test]
+    fn leftover_bytes_and_bad_flags_are_invalid() {
+        let bytes = written();
+        let mut reader = StateReader::new(&bytes).unwrap();
+        assert_eq!(reader.bool(), Err(StateError::Invalid("flag")));
+        assert_eq!(reader.finish(), Err(StateError::Invalid("length")));
+    }
+}
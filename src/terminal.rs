@@ -0,0 +1,56 @@
+use std::io::{self, Read};
+use std::os::unix::io::{AsRawFd, RawFd};
+use termios::*;
+
+/// Puts stdin into non-blocking, unbuffered mode without echo. The original
+/// settings are restored when dropped.
+pub struct RawTerminal {
+    fd: RawFd,
+    original: Termios,
+    raw: Termios,
+}
+
+impl RawTerminal {
+    pub fn enable() -> io::Result<Self> {
+        let fd = io::stdin().as_raw_fd();
+        let original = Termios::from_fd(fd)?;
+        let mut termios = original;
+        // Change input to read buffer rather than line and remove echo
+        termios.c_lflag &= !(ECHO | ICANON);
+        // Reads return straight away with whatever bytes are available
+        termios.c_cc[VMIN] = 0;
+        termios.c_cc[VTIME] = 0;
+        tcsetattr(fd, TCSANOW, &termios)?;
+        Ok(RawTerminal {
+            fd,
+            original,
+            raw: termios,
+        })
+    }
+
+    /// Switch between raw mode and the original line buffered mode, for
+    /// reading whole lines such as debugger commands.
+    pub fn set_raw(&mut self, raw: bool) -> io::Result<()> {
+        let termios = if raw { &self.raw } else { &self.original };
+        tcsetattr(self.fd, TCSANOW, termios)
+    }
+
+    /// Read every byte currently waiting on stdin without blocking.
+    pub fn read_available(&mut self) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        let mut buffer = [0; 64];
+        loop {
+            let count = io::stdin().read(&mut buffer)?;
+            bytes.extend_from_slice(&buffer[..count]);
+            if count < buffer.len() {
+                return Ok(bytes);
+            }
+        }
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let _ = tcsetattr(self.fd, TCSANOW, &self.original);
+    }
+}
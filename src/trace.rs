@@ -0,0 +1,234 @@
+use crate::chip::{Chip8, StepOutcome};
+use crate::error::Chip8Error;
+use crate::instruction::Instruction;
+use std::fmt::Write;
+use std::ops::{Range, RangeInclusive};
+
+/// Which instructions are written to the trace, everything by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    /// Only instructions at these addresses.
+    pub addresses: Option<RangeInclusive<u16>>,
+    /// Only instructions executed during these cycles, counted from 0.
+    pub cycles: Option<Range<u64>>,
+}
+
+impl TraceFilter {
+    fn matches(&self, cycle: u64, pc: u16) -> bool {
+        self.addresses
+            .as_ref()
+            .is_none_or(|addresses| addresses.contains(&pc))
+            && self
+                .cycles
+                .as_ref()
+                .is_none_or(|cycles| cycles.contains(&cycle))
+    }
+}
+
+/// Records a line for every instruction executed through it:
+///
+/// ```text
+///       12 0206 D015      DRW V0, V1, 5         V0:08 V1:04 VF:00->01 I:0005
+/// ```
+///
+/// The cycle count, pc and raw opcode in hex (both words of the four byte
+/// `LD I, LONG`), the mnemonic, then the value
+/// of each register the instruction names or changed and the index
+/// register, written `before->after` when they changed. Spinning on
+/// `LD Vx, K` counts cycles but is not logged.
+#[derive(Debug, Default)]
+pub struct Tracer {
+    filter: TraceFilter,
+    cycle: u64,
+    lines: Vec<String>,
+}
+
+impl Tracer {
+    pub fn new(filter: TraceFilter) -> Self {
+        Tracer {
+            filter,
+            ..Tracer::default()
+        }
+    }
+
+    /// Instructions executed so far.
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Lines traced since the last call, to be written out by the caller.
+    pub fn take_lines(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.lines)
+    }
+
+    /// `Chip8::step`, tracing the instruction executed.
+    pub fn step(&mut self, chip8: &mut Chip8) -> Result<StepOutcome, Chip8Error> {
+        let pc = chip8.pc();
+        let registers = *chip8.registers();
+        let index = chip8.index();
+        let instruction = Instruction::decode_at(chip8.memory(), pc as usize);
+        // Both words of LD I, LONG, as the disassembler shows them
+        let size = instruction.map_or(2, |instruction| instruction.size());
+        let raw: String = chip8
+            .memory()
+            .iter()
+            .skip(pc as usize)
+            .take(size)
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+
+        let outcome = chip8.step()?;
+        if outcome == StepOutcome::WaitingForVblank {
+            return Ok(outcome);
+        }
+        let cycle = self.cycle;
+        self.cycle += 1;
+        if outcome == StepOutcome::WaitingForKey || !self.filter.matches(cycle, pc) {
+            return Ok(outcome);
+        }
+
+        let mnemonic = instruction.map_or_else(String::new, |instruction| instruction.to_string());
+        let mut line = format!("{:>8} {:04X} {:<8}  {:<20} ", cycle, pc, raw, mnemonic);
+        let named = instruction.map_or_else(Vec::new, |instruction| named_registers(&instruction));
+        for (reg, (&before, &after)) in registers.iter().zip(chip8.registers()).enumerate() {
+            if before != after {
+                let _ = write!(line, "V{:X}:{:02X}->{:02X} ", reg, before, after);
+            } else if named.contains(&reg) {
+                let _ = write!(line, "V{:X}:{:02X} ", reg, before);
+            }
+        }
+        let _ = write!(line, "I:{:04X}", index);
+        if chip8.index() != index {
+            let _ = write!(line, "->{:04X}", chip8.index());
+        }
+        self.lines.push(line);
+        Ok(outcome)
+    }
+
+    /// `Chip8::run_frame`, tracing each instruction executed.
+    pub fn run_frame(
+        &mut self,
+        chip8: &mut Chip8,
+        cycles_per_frame: u32,
+    ) -> Result<StepOutcome, Chip8Error> {
+        let mut outcome = StepOutcome::Executed;
+        for _ in 0..cycles_per_frame {
+            outcome = self.step(chip8)?;
+            if matches!(outcome, StepOutcome::WaitingForVblank | StepOutcome::Exit) {
+                break;
+            }
+        }
+        chip8.tick_timers();
+        Ok(outcome)
+    }
+}
+
+// Registers an instruction reads or writes by name
+fn named_registers(instruction: &Instruction) -> Vec<usize> {
+    let (x, y) = match *instruction {
+        Instruction::SaveRange { x, y } | Instruction::LoadRange { x, y } => {
+            return (x.min(y) as usize..=x.max(y) as usize).collect()
+        }
+        Instruction::Store { x }
+        | Instruction::Load { x }
+        | Instruction::StoreFlags { x }
+        | Instruction::LoadFlags { x } => return (0..=x as usize).collect(),
+        // V0 for the original jump, Vx for the SUPER-CHIP quirk
+        Instruction::JumpOffset(addr) => (0, (addr >> 8) as u8),
+        Instruction::SkipEq { x, y }
+        | Instruction::SkipNe { x, y }
+        | Instruction::Move { x, y }
+        | Instruction::Or { x, y }
+        | Instruction::And { x, y }
+        | Instruction::Xor { x, y }
+        | Instruction::Add { x, y }
+        | Instruction::Sub { x, y }
+        | Instruction::ShiftRight { x, y }
+        | Instruction::SubN { x, y }
+        | Instruction::ShiftLeft { x, y }
+        | Instruction::Draw { x, y, .. } => (x, y),
+        Instruction::SkipEqImm { x, .. }
+        | Instruction::SkipNeImm { x, .. }
+        | Instruction::LoadImm { x, .. }
+        | Instruction::AddImm { x, .. }
+        | Instruction::Random { x, .. }
+        | Instruction::SkipKey { x }
+        | Instruction::SkipNotKey { x }
+        | Instruction::LoadDelay { x }
+        | Instruction::WaitKey { x }
+        | Instruction::SetDelay { x }
+        | Instruction::SetSound { x }
+        | Instruction::AddIndex { x }
+        | Instruction::Font { x }
+        | Instruction::BigFont { x }
+        | Instruction::Bcd { x }
+        | Instruction::Pitch { x } => (x, x),
+        _ => return Vec::new(),
+    };
+    vec![x as usize, y as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+
+    // LD V0, 0x08; ADD V0, 0x01; LD I, 0x123
+    const ROM: [u8; 6] = [0x60, 0x08, 0x70, 0x01, 0xA1, 0x23];
+
+    fn trace(filter: TraceFilter) -> Vec<String> {
+        let mut chip8 = Chip8::with_seed(Quirks::chip8(), 0);
+        chip8.load(&ROM, 0x200).unwrap();
+        let mut tracer = Tracer::new(filter);
+        for _ in 0..ROM.len() / 2 {
+            tracer.step(&mut chip8).unwrap();
+        }
+        assert_eq!(tracer.cycle(), 3);
+        tracer.take_lines()
+    }
+
+    #[test]
+    fn lines_show_changed_and_named_registers() {
+        assert_eq!(
+            trace(TraceFilter::default()),
+            [
+                "       0 0200 6008      LD V0, 0x08          V0:00->08 I:0000",
+                "       1 0202 7001      ADD V0, 0x01         V0:08->09 I:0000",
+                "       2 0204 A123      LD I, 0x123          I:0000->0123",
+            ]
+        );
+    }
+
+    #[test]
+    fn filter_by_address() {
+        let lines = trace(TraceFilter {
+            addresses: Some(0x202..=0x203),
+            cycles: None,
+        });
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("       1 0202 "), "{}", lines[0]);
+    }
+
+    #[test]
+    fn filter_by_open_ended_cycles() {
+        let lines = trace(TraceFilter {
+            addresses: None,
+            cycles: Some(1..u64::MAX),
+        });
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("       1 0202 "), "{}", lines[0]);
+        assert!(lines[1].starts_with("       2 0204 "), "{}", lines[1]);
+    }
+
+    #[test]
+    fn long_index_shows_both_words() {
+        let mut chip8 = Chip8::with_seed(Quirks::xochip(), 0);
+        chip8.load(&[0xF0, 0x00, 0x12, 0x34], 0x200).unwrap();
+        let mut tracer = Tracer::new(TraceFilter::default());
+        tracer.step(&mut chip8).unwrap();
+        assert_eq!(
+            tracer.take_lines(),
+            ["       0 0200 F0001234  LD I, LONG 0x1234    I:0000->1234"]
+        );
+    }
+}
@@ -0,0 +1,100 @@
+//! Runs random ROMs for a bounded number of frames under every quirk
+//! profile. Any instruction may fault, but none may panic.
+
+use chip8emu::{Chip8, Instruction, Quirks, StepOutcome};
+use proptest::prelude::*;
+
+const FRAMES: u32 = 30;
+const CYCLES_PER_FRAME: u32 = 50;
+
+fn quirks() -> impl Strategy<Value = Quirks> {
+    prop_oneof![
+        Just(Quirks::chip8()),
+        Just(Quirks::chip48()),
+        Just(Quirks::superchip()),
+        Just(Quirks::xochip()),
+    ]
+}
+
+// Random bytes mostly fault on an invalid opcode straight away, these run for longer
+fn valid_rom() -> impl Strategy<Value = Vec<u8>> {
+    // Invalid opcodes become LD Vx, byte, too many to reject
+    let instruction = (any::<u16>(), any::<u16>()).prop_map(|(opcode, next)| {
+        match Instruction::decode(opcode, Some(next)) {
+            Instruction::Unknown(_) => Instruction::decode(0x6000 | (opcode & 0x0FFF), None),
+            instruction => instruction,
+        }
+    });
+    prop::collection::vec(instruction, 0..256)
+        .prop_map(|instructions| instructions.iter().flat_map(Instruction::encode).collect())
+}
+
+// Run until the ROM exits or faults, pressing and releasing keys as it goes
+fn run(chip8: &mut Chip8, keys: &[(u8, bool)]) {
+    for frame in 0..FRAMES {
+        if let Some(&(key, pressed)) = keys.get(frame as usize) {
+            chip8.set_key(key, pressed);
+        }
+        match chip8.run_frame(CYCLES_PER_FRAME) {
+            Ok(StepOutcome::Exit) | Err(_) => return,
+            Ok(_) => {}
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    fn random_roms_never_panic(
+        rom in prop::collection::vec(any::<u8>(), 0..512),
+        quirks in quirks(),
+        seed in any::<u64>(),
+        keys in prop::collection::vec((0u8..16, any::<bool>()), 0..FRAMES as usize),
+    ) {
+        let mut chip8 = Chip8::with_seed(quirks, seed);
+        chip8.load(&rom, 0x200).unwrap();
+        run(&mut chip8, &keys);
+    }
+
+    #[test]
+    fn random_valid_instructions_never_panic(
+        rom in valid_rom(),
+        quirks in quirks(),
+        seed in any::<u64>(),
+    ) {
+        let mut chip8 = Chip8::with_seed(quirks, seed);
+        chip8.load(&rom, 0x200).unwrap();
+        run(&mut chip8, &[]);
+    }
+
+    // Near the top of memory instructions, sprites and stores run off the end
+    #[test]
+    fn roms_at_the_end_of_memory_never_panic(
+        rom in prop::collection::vec(any::<u8>(), 1..64),
+        quirks in quirks(),
+        seed in any::<u64>(),
+    ) {
+        let mut chip8 = Chip8::with_seed(quirks, seed);
+        let start = (quirks.memory_size - rom.len()) as u16 & !1;
+        chip8.load(&rom[..rom.len() & !1], start).unwrap();
+        run(&mut chip8, &[]);
+    }
+
+    #[test]
+    fn save_states_of_random_roms_load_back(
+        rom in prop::collection::vec(any::<u8>(), 0..512),
+        quirks in quirks(),
+        seed in any::<u64>(),
+        other in quirks(),
+    ) {
+        let mut chip8 = Chip8::with_seed(quirks, seed);
+        chip8.load(&rom, 0x200).unwrap();
+        run(&mut chip8, &[]);
+        let state = chip8.save_state();
+        let mut restored = Chip8::with_seed(quirks, 0);
+        prop_assert!(restored.load_state(&state).is_ok());
+        prop_assert_eq!(&restored.save_state(), &state);
+        // Only loads under the quirks it was saved with
+        let mut other_machine = Chip8::with_seed(other, 0);
+        prop_assert_eq!(other_machine.load_state(&state).is_ok(), other == quirks);
+    }
+}
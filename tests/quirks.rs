@@ -0,0 +1,116 @@
+//! Probes each quirk with a short program under every profile and checks the
+//! behaviour the profile is documented to have, the same behaviours the
+//! Timendus quirks test looks for. Unlike that ROM these need nothing
+//! downloaded, and they also cover chip48, which `tests/test_suite.rs` does
+//! not run the quirks ROM under.
+
+use chip8emu::asm::assemble;
+use chip8emu::{Chip8, Quirks, StepOutcome};
+
+const PROFILES: [&str; 4] = ["chip8", "chip48", "schip", "xochip"];
+
+// Run `source` under `profile` until it exits or `frames` frames have passed
+fn run(profile: &str, source: &str, frames: u32) -> Chip8 {
+    let rom = assemble(source, 0x200).unwrap();
+    let mut chip8 = Chip8::with_seed(Quirks::from_name(profile).unwrap(), 0);
+    chip8.load(&rom, 0x200).unwrap();
+    for _ in 0..frames {
+        if chip8.run_frame(10).unwrap() == StepOutcome::Exit {
+            break;
+        }
+    }
+    chip8
+}
+
+// Check `probe` against the expected value for each profile, in PROFILES order
+fn check<T: PartialEq + std::fmt::Debug>(
+    source: &str,
+    frames: u32,
+    expected: [T; 4],
+    probe: impl Fn(&Chip8) -> T,
+) {
+    for (profile, expected) in PROFILES.into_iter().zip(expected) {
+        let chip8 = run(profile, source, frames);
+        assert_eq!(probe(&chip8), expected, "under {}", profile);
+    }
+}
+
+#[test]
+fn vf_reset() {
+    let source = "
+        LD VF, 5
+        LD V0, 1
+        OR V0, V0
+        EXIT";
+    check(source, 1, [0, 5, 5, 5], |chip8| chip8.registers()[0xF]);
+}
+
+#[test]
+fn memory_increments_index() {
+    let source = "
+        LD I, 0x300
+        LD [I], V2
+        EXIT";
+    check(source, 1, [0x303, 0x302, 0x300, 0x303], |chip8| {
+        chip8.index()
+    });
+}
+
+#[test]
+fn display_wait() {
+    // With the quirk only the first sprite is drawn in the first frame
+    let source = "
+        DRW V0, V0, 1
+        DRW V0, V0, 1
+    loop:
+        JP loop";
+    check(source, 1, [0x202, 0x204, 0x204, 0x204], |chip8| chip8.pc());
+}
+
+#[test]
+fn clipping() {
+    // The top row of the font's 0 is four pixels wide, drawn from x 62 the
+    // last two wrap round to x 0 and 1 unless clipped
+    let source = "
+        LD V0, 0
+        LD F, V0
+        LD V1, 62
+        DRW V1, V0, 5
+        EXIT";
+    check(source, 1, [false, false, false, true], |chip8| {
+        chip8.framebuffer().pixels()[0] != 0
+    });
+}
+
+#[test]
+fn shifting() {
+    // In place shifts V0, otherwise V1 is shifted into V0
+    let source = "
+        LD V0, 0x10
+        LD V1, 0x04
+        SHR V0, V1
+        EXIT";
+    check(source, 1, [0x02, 0x08, 0x08, 0x02], |chip8| {
+        chip8.registers()[0]
+    });
+}
+
+#[test]
+fn jumping() {
+    // JP V0, 0x210 adds V2 instead of V0 with the quirk
+    let source = "
+        LD V0, 0
+        LD V2, 4
+        JP V0, 0x210";
+    // Stop right after the jump, nothing is loaded at its targets
+    let expected = [0x210, 0x214, 0x214, 0x210];
+    for (profile, expected) in PROFILES.into_iter().zip(expected) {
+        let rom = assemble(source, 0x200).unwrap();
+        let mut chip8 = Chip8::with_seed(Quirks::from_name(profile).unwrap(), 0);
+        chip8.load(&rom, 0x200).unwrap();
+        for _ in 0..3 {
+            chip8.step().unwrap();
+        }
+        assert_eq!(chip8.pc(), expected, "under {}", profile);
+    }
+}
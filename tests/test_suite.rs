@@ -0,0 +1,287 @@
+//! Runs the Timendus CHIP-8 test suite, https://github.com/Timendus/chip8-test-suite,
+//! headlessly under every quirk profile and compares the final display with
+//! the expected one checked in under `tests/expected`.
+//!
+//! The suite's ROMs are not part of this repository, so these tests are
+//! ignored by default. Fetch them into `tests/roms` with
+//! `scripts/fetch-test-suite.sh`, or point `CHIP8_TEST_SUITE` at the suite's
+//! `bin` directory, then run `cargo test --test test_suite -- --ignored`. A
+//! missing ROM fails its test.
+//!
+//! Expected displays are text, a character per pixel: `.` for off, `#` for
+//! the first plane, `+` for the second and `%` for both. Run with
+//! `CHIP8_BLESS=1` to write them from the current output, then check each one
+//! by eye against the screens in the suite's documentation before committing.
+//!
+//! A few cases are assembled from source here rather than read from the
+//! suite, so the harness and its expected displays are exercised without
+//! anything downloaded.
+
+use chip8emu::asm::assemble;
+use chip8emu::keyscript::KeyScript;
+use chip8emu::{Chip8, Framebuffer, Quirks, StepOutcome};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+const PROFILES: [&str; 4] = ["chip8", "chip48", "schip", "xochip"];
+const CYCLES_PER_FRAME: u32 = 30;
+
+/// One suite ROM and how to drive it.
+struct Case {
+    rom: &'static str,
+    // Assembled in place of reading `rom`, which then only names the expected displays
+    source: Option<&'static str>,
+    frames: u64,
+    keys: &'static str,
+    // Written to 0x1FF before starting, how the suite picks a platform or test without a menu.
+    // Profiles without a choice are not run.
+    choice: Option<fn(&str) -> Option<u8>>,
+}
+
+fn rom_dir() -> PathBuf {
+    match env::var_os("CHIP8_TEST_SUITE") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/roms"),
+    }
+}
+
+fn expected_path(rom: &str, profile: &str) -> PathBuf {
+    let name = rom.trim_end_matches(".ch8");
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join(format!("tests/expected/{}-{}.txt", name, profile))
+}
+
+fn display_text(display: &Framebuffer) -> String {
+    let mut text = String::new();
+    for row in display.rows() {
+        text.extend(row.iter().map(|pixel| match pixel & 0x3 {
+            0 => '.',
+            1 => '#',
+            2 => '+',
+            _ => '%',
+        }));
+        text.push('\n');
+    }
+    text
+}
+
+// Run the case under every profile it has a choice for
+fn run(case: &Case) -> Vec<(&'static str, Chip8)> {
+    let rom = match case.source {
+        Some(source) => assemble(source, 0x200).expect("bad source"),
+        None => {
+            let path = rom_dir().join(case.rom);
+            fs::read(&path).unwrap_or_else(|err| {
+                panic!(
+                    "could not read {}: {}, fetch the suite with scripts/fetch-test-suite.sh",
+                    path.display(),
+                    err
+                )
+            })
+        }
+    };
+    let keys = KeyScript::parse(case.keys).expect("bad key script");
+
+    let mut finished = Vec::new();
+    for profile in PROFILES {
+        let mut chip8 = Chip8::with_seed(Quirks::from_name(profile).unwrap(), 0);
+        if let Some(choice) = case.choice {
+            let Some(choice) = choice(profile) else {
+                continue;
+            };
+            chip8.load(&[choice], 0x1FF).unwrap();
+        }
+        chip8.load(&rom, 0x200).unwrap();
+        for frame in 0..case.frames {
+            keys.apply(frame, &mut chip8);
+            match chip8.run_frame(CYCLES_PER_FRAME) {
+                Ok(StepOutcome::Exit) => break,
+                Ok(_) => {}
+                Err(err) => panic!(
+                    "{} under {} faulted on frame {}: {}",
+                    case.rom, profile, frame, err
+                ),
+            }
+        }
+        finished.push((profile, chip8));
+    }
+    finished
+}
+
+fn check(case: Case) -> Vec<(&'static str, Chip8)> {
+    let finished = run(&case);
+    let bless = env::var_os("CHIP8_BLESS").is_some();
+    for (profile, chip8) in &finished {
+        let actual = display_text(chip8.framebuffer());
+        let path = expected_path(case.rom, profile);
+        if bless {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, &actual).unwrap();
+            continue;
+        }
+        let expected = fs::read_to_string(&path).unwrap_or_else(|_| {
+            panic!(
+                "no expected display at {}, run with CHIP8_BLESS=1",
+                path.display()
+            )
+        });
+        assert_eq!(
+            actual,
+            expected,
+            "{} under {} does not match {}",
+            case.rom,
+            profile,
+            path.display()
+        );
+    }
+    finished
+}
+
+// The quirks test skips its menu when 0x1FF holds a platform. Its menu offers
+// CHIP-8, SUPER-CHIP and XO-CHIP but no CHIP-48, and checking chip48 against
+// another platform's expected quirks would prove nothing, so chip48 is left
+// out here. tests/quirks.rs checks each chip48 quirk directly instead.
+fn platform(profile: &str) -> Option<u8> {
+    match profile {
+        "chip8" => Some(1),
+        "schip" => Some(2),
+        "xochip" => Some(3),
+        _ => None,
+    }
+}
+
+#[test]
+fn font() {
+    // All sixteen font digits in two rows, then a 0 at x 62 whose right half
+    // wraps round to the left edge under xochip and is clipped elsewhere
+    let source = "
+        LD V0, 0
+        LD V1, 1
+        LD V2, 1
+    top:
+        LD F, V0
+        DRW V1, V2, 5
+        ADD V1, 5
+        ADD V0, 1
+        SE V0, 8
+        JP top
+        LD V1, 1
+        LD V2, 8
+    bottom:
+        LD F, V0
+        DRW V1, V2, 5
+        ADD V1, 5
+        ADD V0, 1
+        SE V0, 16
+        JP bottom
+        LD V0, 0
+        LD F, V0
+        LD V1, 62
+        LD V2, 20
+        DRW V1, V2, 5
+    done:
+        JP done";
+    check(Case {
+        rom: "font.ch8",
+        source: Some(source),
+        frames: 60,
+        keys: "",
+        choice: None,
+    });
+}
+
+#[test]
+#[ignore = "needs the suite's ROMs, see scripts/fetch-test-suite.sh"]
+fn chip8_logo() {
+    check(Case {
+        rom: "1-chip8-logo.ch8",
+        frames: 60,
+        source: None,
+        keys: "",
+        choice: None,
+    });
+}
+
+#[test]
+#[ignore = "needs the suite's ROMs, see scripts/fetch-test-suite.sh"]
+fn ibm_logo() {
+    check(Case {
+        rom: "2-ibm-logo.ch8",
+        frames: 60,
+        source: None,
+        keys: "",
+        choice: None,
+    });
+}
+
+#[test]
+#[ignore = "needs the suite's ROMs, see scripts/fetch-test-suite.sh"]
+fn corax_plus() {
+    check(Case {
+        rom: "3-corax+.ch8",
+        frames: 120,
+        source: None,
+        keys: "",
+        choice: None,
+    });
+}
+
+#[test]
+#[ignore = "needs the suite's ROMs, see scripts/fetch-test-suite.sh"]
+fn flags() {
+    check(Case {
+        rom: "4-flags.ch8",
+        frames: 240,
+        source: None,
+        keys: "",
+        choice: None,
+    });
+}
+
+#[test]
+#[ignore = "needs the suite's ROMs, see scripts/fetch-test-suite.sh"]
+fn quirks() {
+    check(Case {
+        rom: "5-quirks.ch8",
+        frames: 600,
+        source: None,
+        keys: "",
+        choice: Some(platform),
+    });
+}
+
+#[test]
+#[ignore = "needs the suite's ROMs, see scripts/fetch-test-suite.sh"]
+fn keypad() {
+    // The GETKEY test, press and release 5
+    let keys = "30 5 down\n40 5 up\n";
+    check(Case {
+        rom: "6-keypad.ch8",
+        frames: 120,
+        source: None,
+        keys,
+        choice: Some(|_| Some(3)),
+    });
+}
+
+#[test]
+#[ignore = "needs the suite's ROMs, see scripts/fetch-test-suite.sh"]
+fn beep() {
+    // Beeps for as long as B is held
+    let keys = "30 B down\n";
+    let finished = check(Case {
+        rom: "7-beep.ch8",
+        frames: 120,
+        source: None,
+        keys,
+        choice: None,
+    });
+    for (profile, chip8) in finished {
+        assert!(
+            chip8.timers().1 > 0,
+            "no sound with B held under {}",
+            profile
+        );
+    }
+}